@@ -1,25 +1,21 @@
-use std::collections::HashSet;
-
-use awa5_rs::*;
+use awa5_rs::Compiler;
 
 fn main() {
-    let path = "/usr/local/lib;/assets";
-    let include_paths = "/assets";
-    let lines = read_lines("/assets/raylib3d.awasm").unwrap();
-
-    let mut macro_table = parser::awasm::MacroTable::new();
-    let mut already_included: HashSet<String> = HashSet::new();
-    let mut label_included: HashSet<String> = HashSet::new();
-    let instructions = parser::awasm::parse_lines(
-        &mut macro_table,
-        &mut already_included,
-        &mut label_included,
-        include_paths,
-        "",
-        lines,
-    );
-
-    let object_vec = assembler::make_object_vec(&instructions);
+    let result = Compiler::new()
+        .include_path("/assets")
+        .library_path("/usr/local/lib")
+        .library_path("/assets")
+        .compile_file("/assets/raylib3d.awasm");
 
-    interpet_object(object_vec, path);
+    match result {
+        Ok(program) => {
+            if let Err(err) = program.run() {
+                eprintln!("Runtime error: {}", err);
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
 }
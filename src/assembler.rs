@@ -1,11 +1,11 @@
-use std::collections::HashMap;
-
+use crate::linker::{self, ObjectFile};
+use crate::parser::awasm::Origin;
 use crate::{Awatism, Instruction};
 
 pub fn assemble_awatism(instruction: &Instruction) -> Vec<u8> {
     match &instruction.awatism {
         Awatism::Nop => {
-            let bytes = vec![0x01, 0x00];
+            let bytes = vec![0x00, 0x00];
             bytes
         }
         Awatism::Prn => {
@@ -88,6 +88,14 @@ pub fn assemble_awatism(instruction: &Instruction) -> Vec<u8> {
             let bytes = vec![0x14, 0x00];
             bytes
         }
+        Awatism::Syscall => {
+            let bytes = vec![0x15, 0x00];
+            bytes
+        }
+        Awatism::DoublePop => {
+            let bytes = vec![0x16, 0x00];
+            bytes
+        }
         Awatism::Lib => {
             let bytes = vec![0x17, 0x00];
             bytes
@@ -120,181 +128,196 @@ pub fn assemble_awatism(instruction: &Instruction) -> Vec<u8> {
     }
 }
 
+/// Assemble instructions to a flat object, label positions are taken from the emitted
+/// code so labels take no space and `call` without a label takes one instruction
 pub fn make_object_vec(instructions: &[Instruction]) -> Vec<u8> {
-    let mut vec = Vec::new();
+    let object = ObjectFile::assemble(instructions, |_| true);
+    linker::link(&[("input".to_string(), object)]).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    let mut labels: HashMap<String, usize> = HashMap::new();
-    let mut jumps: HashMap<String, usize> = HashMap::new();
+/// Listing of every object instruction with its index, bytes, awatalk bits and the source
+/// line and macros it came from, `origins` are in the same order as `instructions`
+///
+/// Offsets of `jro` and `call` stay zero when their label is not defined in the instructions
+pub fn make_listing(instructions: &[Instruction], origins: &[Origin]) -> String {
+    let code = ObjectFile::assemble(instructions, |_| true).resolve_local();
 
-    let mut parent_label = String::new();
-    let mut current: usize = 0;
-    for instruction in instructions {
-        match &instruction.awatism {
-            Awatism::StrLbl(label) => {
-                parent_label = label.to_string();
-                labels.insert(label.to_string(), current);
-            }
-            Awatism::JmpRelStr(label) => {
-                current += 6;
-                if label.contains(".") {
-                    jumps.insert(parent_label.to_string() + label, current);
+    let mut result = String::new();
+    let mut index = 0;
+    let mut last_origin = None;
+    for (i, instruction) in instructions.iter().enumerate() {
+        let origin = origins.get(i);
+        let source = match origin {
+            Some(origin) if last_origin != Some((&origin.path, origin.line)) => {
+                last_origin = Some((&origin.path, origin.line));
+                let location = if origin.path.is_empty() {
+                    origin.line.to_string()
                 } else {
-                    jumps.insert(label.to_string(), current);
-                }
-            }
-            Awatism::Call(_, _) => {
-                current += 6;
-            }
-            _ => {
-                current += 1;
-            }
-        }
-    }
+                    format!("{}:{}", origin.path, origin.line)
+                };
+                format!("{}  {}", location, origin.text.trim())
+            }
+            _ => String::new(),
+        };
+        let macros = origin
+            .filter(|origin| !origin.macros.is_empty())
+            .map(|origin| format!("  [!{}]", origin.macros.join(" > !")))
+            .unwrap_or_default();
 
-    let mut tmp;
-
-    for instruction in instructions {
+        let size = ObjectFile::assemble(std::slice::from_ref(instruction), |_| true)
+            .code
+            .len()
+            / 2;
         match &instruction.awatism {
             Awatism::StrLbl(label) => {
-                if !label.contains(".") {
-                    parent_label = label.to_string();
-                }
-            }
-            Awatism::JmpRelStr(_label) => {
-                let mut label = _label;
-                if label.starts_with(".") {
-                    tmp = parent_label.clone();
-                    tmp = tmp + label;
-                    label = &tmp;
-                }
-                let mut res = Vec::new();
-                let label_pos = *labels.get(label).unwrap() as i32;
-                let current_pos = *jumps.get(label).unwrap() as i32;
-                let jump_val = label_pos - current_pos;
-                for byte in i32::to_le_bytes(jump_val as i32) {
-                    res.extend(vec![0x05, byte]); // blo i32 little endian
-                }
-                res.extend(vec![0x09, 4]); // srn 4
-                vec.extend(res);
-            }
-            Awatism::Call(is_string, string) => {
-                if is_string.clone() {
-                    let mut res = Vec::new();
-                    let label_pos = *labels.get(string).unwrap() as i32;
-                    for byte in i32::to_le_bytes(label_pos) {
-                        res.extend(vec![0x05, byte]); // blo i32 little endian
+                let line = format!("{:>6}  {:<63}  {}", "", format!("{}:", label), source);
+                result += line.trim_end();
+                result += "\n";
+            }
+            awatism => {
+                for slot in index..index + size {
+                    let (op, arg) = (code[slot * 2], code[slot * 2 + 1]);
+                    let mut bits = to_mapping_op(op);
+                    if Awatism::needs_args(op) {
+                        bits += &to_mapping_arg(arg, Awatism::arg_bits(op));
+                    }
+                    let mut comment = String::new();
+                    if slot == index {
+                        comment = source.clone() + &macros;
                     }
-                    res.extend(vec![0x09, 4]); // srn 4
-                    vec.extend(res);
+                    if slot == index + size - 1 {
+                        if let Awatism::JmpRelStr(label) | Awatism::Call(true, label) = awatism {
+                            comment += &format!("  -> {}", label);
+                        }
+                    }
+                    let line = format!(
+                        "{:>6}  {:02x} {:02x}  {:<56}  {}",
+                        slot,
+                        op,
+                        arg,
+                        bits.trim_start(),
+                        comment
+                    );
+                    result += line.trim_end();
+                    result += "\n";
                 }
             }
-            _ => {}
         }
-        vec.extend(assemble_awatism(&instruction));
+        index += size;
     }
-
-    vec
+    result
 }
 
 pub fn object_to_awasm(vec: &Vec<u8>) -> String {
     let mut result = String::new();
     let mut i = 0;
     while i < vec.len() {
-        let op = vec[i];
-        let arg = vec[i + 1];
-        match Awatism::from_u8(op, arg).unwrap() {
-            Awatism::Nop => {
-                result += "nop";
-            }
-            Awatism::Prn => {
-                result += "prn";
-            }
-            Awatism::Pr1 => {
-                result += "pr1";
-            }
-            Awatism::Red => {
-                result += "red";
-            }
-            Awatism::R3d => {
-                result += "r3d";
-            }
-            Awatism::Blo(arg) => {
-                result += &format!("blo {}", arg);
-            }
-            Awatism::Sbm(arg) => {
-                result += &format!("sbm {}", arg);
-            }
-            Awatism::Pop => {
-                result += "pop";
-            }
-            Awatism::Dpl => {
-                result += "dpl";
-            }
-            Awatism::Srn(arg) => {
-                result += &format!("srn {}", arg);
-            }
-            Awatism::Mrg => {
-                result += "mrg";
-            }
-            Awatism::Add => {
-                result += "4dd";
-            }
-            Awatism::Sub => {
-                result += "sub";
-            }
-            Awatism::Mul => {
-                result += "mul";
-            }
-            Awatism::Div => {
-                result += "div";
-            }
-            Awatism::Cnt => {
-                result += "cnt";
-            }
-            Awatism::Lbl(arg) => {
-                result += &format!("lbl {}", arg);
-            }
-            Awatism::Jmp(arg) => {
-                result += &format!("jmp {}", arg);
-            }
-            Awatism::Eql => {
-                result += "eql";
-            }
-            Awatism::Lss => {
-                result += "lss";
-            }
-            Awatism::Gr8 => {
-                result += "gr8";
-            }
-            Awatism::Lib => {
-                result += "lib";
-            }
-            Awatism::Call(_, _) => {
-                result += "call";
-            }
-            Awatism::Ret => {
-                result += "ret";
-            }
-            Awatism::Trm => {
-                result += "trm";
-            }
-            // special awatism
-            Awatism::StrLbl(_str_label) => {
-                // only used to calculate position of relative jump from awasm label
-            }
-            Awatism::JmpRel => {
-                result += "jro";
-            }
-            Awatism::JmpRelStr(_) => {
-                result += "jro";
-            }
-        }
+        result += &instruction_to_awasm(vec[i], vec[i + 1]);
         result += "\n";
         i += 2;
     }
     result
 }
 
+/// Awasm line of one object instruction
+fn instruction_to_awasm(op: u8, arg: u8) -> String {
+    let mut result = String::new();
+    match Awatism::from_u8(op, arg).unwrap() {
+        Awatism::Nop => {
+            result += "nop";
+        }
+        Awatism::Prn => {
+            result += "prn";
+        }
+        Awatism::Pr1 => {
+            result += "pr1";
+        }
+        Awatism::Red => {
+            result += "red";
+        }
+        Awatism::R3d => {
+            result += "r3d";
+        }
+        Awatism::Blo(arg) => {
+            result += &format!("blo {}", arg);
+        }
+        Awatism::Sbm(arg) => {
+            result += &format!("sbm {}", arg);
+        }
+        Awatism::Pop => {
+            result += "pop";
+        }
+        Awatism::Dpl => {
+            result += "dpl";
+        }
+        Awatism::Srn(arg) => {
+            result += &format!("srn {}", arg);
+        }
+        Awatism::Mrg => {
+            result += "mrg";
+        }
+        Awatism::Add => {
+            result += "4dd";
+        }
+        Awatism::Sub => {
+            result += "sub";
+        }
+        Awatism::Mul => {
+            result += "mul";
+        }
+        Awatism::Div => {
+            result += "div";
+        }
+        Awatism::Cnt => {
+            result += "cnt";
+        }
+        Awatism::Lbl(arg) => {
+            result += &format!("lbl {}", arg);
+        }
+        Awatism::Jmp(arg) => {
+            result += &format!("jmp {}", arg);
+        }
+        Awatism::Eql => {
+            result += "eql";
+        }
+        Awatism::Lss => {
+            result += "lss";
+        }
+        Awatism::Gr8 => {
+            result += "gr8";
+        }
+        Awatism::Syscall => {
+            result += "sys";
+        }
+        Awatism::DoublePop => {
+            result += "p0p";
+        }
+        Awatism::Lib => {
+            result += "lib";
+        }
+        Awatism::Call(_, _) => {
+            result += "call";
+        }
+        Awatism::Ret => {
+            result += "ret";
+        }
+        Awatism::Trm => {
+            result += "trm";
+        }
+        // special awatism
+        Awatism::StrLbl(_str_label) => {
+            // only used to calculate position of relative jump from awasm label
+        }
+        Awatism::JmpRel => {
+            result += "jro";
+        }
+        Awatism::JmpRelStr(_) => {
+            result += "jro";
+        }
+    }
+    result
+}
+
 pub fn object_to_awa(vec: &Vec<u8>) -> String {
     let mut result = String::from("awa");
     let mut i = 0;
@@ -314,26 +337,73 @@ pub fn object_to_awa(vec: &Vec<u8>) -> String {
     result
 }
 
-fn to_mapping_op(num: u8) -> String {
-    let mapping = [" awa", "wa"];
-    let mut result = String::new();
+/// Layout of generated awatalk, the decoder ignores line breaks and `;` comments
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AwaFormat {
+    /// Break lines before they get longer than this many characters
+    pub wrap: Option<usize>,
+    /// Start every instruction on a new line
+    pub per_line: bool,
+    /// Follow every instruction with a `;` comment of its awasm, implies `per_line`
+    pub annotate: bool,
+}
 
-    let start_pos = 8 - 5;
-    for i in start_pos..8 {
-        let bit = (num >> (7 - i)) & 1;
-        result += mapping[bit as usize];
+pub fn object_to_awa_formatted(vec: &[u8], format: &AwaFormat) -> String {
+    let per_line = format.per_line || format.annotate;
+    let mut lines: Vec<(String, String)> = Vec::new();
+    let mut line = String::from("awa");
+    let mut comment = String::new();
+
+    for pair in vec.chunks_exact(2) {
+        let (op, arg) = (pair[0], pair[1]);
+        if per_line {
+            lines.push((std::mem::take(&mut line), std::mem::take(&mut comment)));
+        }
+
+        let mut units = bit_units(op, 5);
+        if Awatism::needs_args(op) {
+            units.extend(bit_units(arg, Awatism::arg_bits(op)));
+        }
+        for unit in units {
+            if let Some(wrap) = format.wrap {
+                if !line.is_empty() && line.len() + unit.len() > wrap {
+                    lines.push((std::mem::take(&mut line), String::new()));
+                }
+            }
+            line += unit;
+        }
+
+        if format.annotate {
+            comment = instruction_to_awasm(op, arg);
+        }
+    }
+    lines.push((line, comment));
+
+    let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    let mut result = String::new();
+    for (line, comment) in lines {
+        if comment.is_empty() {
+            result += &line;
+        } else {
+            result += &format!("{:<width$}  ; {}", line, comment, width = width);
+        }
+        result += "\n";
     }
     result
 }
 
+fn to_mapping_op(num: u8) -> String {
+    to_mapping_arg(num, 5)
+}
+
 fn to_mapping_arg(num: u8, size: usize) -> String {
-    let mapping = [" awa", "wa"];
-    let mut result = String::new();
+    bit_units(num, size).concat()
+}
 
-    let start_pos = 8 - size;
-    for i in start_pos..8 {
-        let bit = (num >> (7 - i)) & 1;
-        result += mapping[bit as usize];
-    }
-    result
+/// ` awa` for each 0 and `wa` for each 1 of the lowest `size` bits
+fn bit_units(num: u8, size: usize) -> Vec<&'static str> {
+    let mapping = [" awa", "wa"];
+    (8 - size..8)
+        .map(|i| mapping[((num >> (7 - i)) & 1) as usize])
+        .collect()
 }
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use crate::lexer::{self, ParseError, Span, Token, TokenKind};
+
+/// A single awasm statement, macros are expanded on token lines before they are parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// `name:` or `.name:`
+    Label { name: String, span: Span },
+    /// Awatism mnemonic with an optional argument, e.g. `blo 5` or `jro .loop`
+    Instruction {
+        mnemonic: String,
+        arg: Option<Token>,
+        span: Span,
+    },
+    /// `!name` followed by the remaining tokens of the line
+    Directive {
+        name: String,
+        tokens: Vec<Token>,
+        span: Span,
+    },
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Label { span, .. }
+            | Statement::Instruction { span, .. }
+            | Statement::Directive { span, .. } => *span,
+        }
+    }
+}
+
+/// Directive arguments separated by commas outside of parentheses
+pub fn split_args(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut args = Vec::new();
+    if tokens.is_empty() {
+        return args;
+    }
+
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::LParen => depth += 1,
+            TokenKind::RParen => depth -= 1,
+            TokenKind::Comma if depth == 0 => {
+                args.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(&tokens[start..]);
+    args
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::Label { name, .. } => write!(f, "{}:", name),
+            Statement::Instruction { mnemonic, arg, .. } => match arg {
+                Some(arg) => write!(f, "{} {}", mnemonic, arg),
+                None => write!(f, "{}", mnemonic),
+            },
+            Statement::Directive { name, tokens, .. } => {
+                write!(f, "!{}", name)?;
+                for (i, token) in tokens.iter().enumerate() {
+                    match token.kind {
+                        TokenKind::Comma | TokenKind::RParen => {}
+                        TokenKind::LParen if i > 0 => {}
+                        _ if i > 0 && tokens[i - 1].kind == TokenKind::LParen => {}
+                        _ => write!(f, " ")?,
+                    }
+                    write!(f, "{}", token)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Group tokens into lines, dropping empty ones
+pub fn split_lines(tokens: Vec<Token>) -> Vec<Vec<Token>> {
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    for token in tokens {
+        if token.kind == TokenKind::Newline {
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+        } else {
+            line.push(token);
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn describe(token: &Token) -> String {
+    match &token.kind {
+        TokenKind::Newline => "end of line".to_string(),
+        _ => format!("'{}'", token),
+    }
+}
+
+/// Parse the tokens of one line, a label may be followed by a statement on the same line
+pub fn parse_line(tokens: &[Token]) -> Result<Vec<Statement>, ParseError> {
+    let mut statements = Vec::new();
+    let mut rest = tokens;
+
+    if let Some(Token {
+        kind: TokenKind::Label(name),
+        span,
+    }) = rest.first()
+    {
+        statements.push(Statement::Label {
+            name: name.clone(),
+            span: *span,
+        });
+        rest = &rest[1..];
+    }
+
+    let (first, last) = match (rest.first(), rest.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(statements),
+    };
+    let span = first.span.to(&last.span);
+
+    match &first.kind {
+        TokenKind::Directive(name) => statements.push(Statement::Directive {
+            name: name.clone(),
+            tokens: rest[1..].to_vec(),
+            span,
+        }),
+        TokenKind::Ident(mnemonic) => {
+            if rest.len() > 2 {
+                return Err(ParseError::new(
+                    format!("Unexpected {} after '{}'", describe(&rest[2]), mnemonic),
+                    rest[2].span,
+                ));
+            }
+            statements.push(Statement::Instruction {
+                mnemonic: mnemonic.clone(),
+                arg: rest.get(1).cloned(),
+                span,
+            });
+        }
+        _ => {
+            return Err(ParseError::new(
+                format!("Expected an instruction, found {}", describe(first)),
+                first.span,
+            ));
+        }
+    }
+
+    Ok(statements)
+}
+
+/// Parse awasm source without expanding macros or includes
+pub fn parse_program(source: &str) -> Result<Vec<Statement>, ParseError> {
+    let mut statements = Vec::new();
+    for line in split_lines(lexer::tokenize(source)?) {
+        statements.extend(parse_line(&line)?);
+    }
+    Ok(statements)
+}
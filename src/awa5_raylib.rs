@@ -1,4 +1,11 @@
-extern {
+use std::ptr;
+
+use crate::{dynlib::bytes_to_bubbles, Interpreter, LibArg, LibResult};
+
+type LibFnWithArgs = unsafe extern "C" fn(*const u8, *mut *mut u8, *mut usize);
+type LibFnNoArgs = unsafe extern "C" fn();
+
+extern "C" {
     pub fn initwindow(data: *const u8, out: *mut *mut u8, out_len: *mut usize);
     pub fn settargetfps(data: *const u8, out: *mut *mut u8, out_len: *mut usize);
     pub fn clearbackground(data: *const u8, out: *mut *mut u8, out_len: *mut usize);
@@ -23,8 +30,68 @@ extern {
     pub fn addfloat(data: *const u8, out: *mut *mut u8, out_len: *mut usize);
 }
 
-extern {
+extern "C" {
     pub fn BeginDrawing();
     pub fn EndDrawing();
     pub fn EndMode3D();
 }
+
+fn call_with_args(lib_fn: LibFnWithArgs, args: &[LibArg]) -> LibResult {
+    let data: Vec<u8> = args.iter().flat_map(LibArg::to_bytes).collect();
+    let mut buffer: *mut u8 = ptr::null_mut();
+    let mut buffer_len: usize = 0;
+
+    unsafe {
+        lib_fn(data.as_ptr(), &mut buffer, &mut buffer_len);
+
+        if !buffer.is_null() {
+            return Ok(bytes_to_bubbles(Vec::from_raw_parts(
+                buffer, buffer_len, buffer_len,
+            )));
+        }
+    }
+    Ok(vec![])
+}
+
+/// Register the statically linked raylib bindings as native lib functions
+pub fn register(interpreter: &mut Interpreter) {
+    let with_args: [(&str, LibFnWithArgs); 22] = [
+        ("initwindow", initwindow),
+        ("settargetfps", settargetfps),
+        ("clearbackground", clearbackground),
+        ("drawtext", drawtext),
+        ("iskeydown", iskeydown),
+        ("drawcircle", drawcircle),
+        ("setcameraposition", setcameraposition),
+        ("setcameratarget", setcameratarget),
+        ("setcameraup", setcameraup),
+        ("setcamerafovy", setcamerafovy),
+        ("setcameraprojection", setcameraprojection),
+        ("beginmode3d", beginmode3d),
+        ("drawcube", drawcube),
+        ("drawcubewires", drawcubewires),
+        ("drawgrid", drawgrid),
+        ("loadmodel", loadmodel),
+        ("unloadmodel", unloadmodel),
+        ("drawmodel", drawmodel),
+        ("drawmodelex", drawmodelex),
+        ("loadtexture", loadtexture),
+        ("setmaterialtexture", setmaterialtexture),
+        ("addfloat", addfloat),
+    ];
+    for (name, lib_fn) in with_args {
+        interpreter.register_fn(name, move |args: &[LibArg]| call_with_args(lib_fn, args));
+    }
+
+    let no_args: [(&str, LibFnNoArgs); 3] = [
+        ("BeginDrawing", BeginDrawing),
+        ("EndDrawing", EndDrawing),
+        ("EndMode3D", EndMode3D),
+    ];
+    for (name, lib_fn) in no_args {
+        interpreter.register_fn(name, move |_: &[LibArg]| {
+            unsafe { lib_fn() };
+            Ok(vec![])
+        });
+    }
+}
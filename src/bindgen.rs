@@ -0,0 +1,331 @@
+use std::fmt::Write;
+
+/// Argument and return types of a binding, matching the `lib` argument type tags
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BindType {
+    Void,
+    I32,
+    F32,
+    Chr,
+    Str,
+    Bubble,
+}
+
+impl BindType {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "void" => Some(BindType::Void),
+            "i32" => Some(BindType::I32),
+            "f32" => Some(BindType::F32),
+            "chr" => Some(BindType::Chr),
+            "str" => Some(BindType::Str),
+            "bubble" => Some(BindType::Bubble),
+            _ => None,
+        }
+    }
+
+    fn c_type(&self) -> &str {
+        match self {
+            BindType::Void => "void",
+            BindType::I32 | BindType::Bubble => "int32_t",
+            BindType::F32 => "float",
+            BindType::Chr => "char",
+            BindType::Str => "const char *",
+        }
+    }
+
+    /// awasm line pushing a macro argument with its type tag
+    fn awasm_push(&self, arg: &str) -> String {
+        match self {
+            BindType::I32 => format!("!_i32 ${}", arg),
+            BindType::F32 => format!("!_f32 ${}", arg),
+            BindType::Chr => format!("!_chr ${}", arg),
+            BindType::Str => format!("!_str ${}", arg),
+            BindType::Bubble => format!("blo 5\nblo ${}\nsrn 2", arg),
+            BindType::Void => String::new(),
+        }
+    }
+}
+
+/// A function declared in a binding file
+#[derive(Debug, Clone)]
+pub struct Binding {
+    /// Symbol called with `lib`, also used as the awasm macro name
+    pub name: String,
+    /// C function called by the shim
+    pub target: String,
+    pub ret: BindType,
+    pub params: Vec<(BindType, String)>,
+}
+
+/// Parse a binding file, one declaration per line and `#` starts a comment
+///
+/// ```text
+/// void initwindow(i32 width, i32 height, str title) = InitWindow
+/// i32 iskeydown(i32 key) = IsKeyDown
+/// void BeginDrawing()
+/// ```
+///
+/// The return type defaults to `void` and the target to `<name>_impl`. Parameter types
+/// are `i32`, `f32`, `chr`, `str` and `bubble` for a simple bubble pushed with `blo`,
+/// chars and strings may be passed as ascii or AWA-SCII literals
+pub fn parse_bindings(content: &str) -> Result<Vec<Binding>, String> {
+    let mut bindings = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let binding = parse_binding(line).map_err(|err| format!("line {}: {}", number + 1, err))?;
+        if bindings.iter().any(|b: &Binding| b.name == binding.name) {
+            return Err(format!(
+                "line {}: '{}' declared twice",
+                number + 1,
+                binding.name
+            ));
+        }
+        bindings.push(binding);
+    }
+
+    Ok(bindings)
+}
+
+fn parse_binding(line: &str) -> Result<Binding, String> {
+    let (decl, target) = match line.split_once('=') {
+        Some((decl, target)) => (decl.trim(), Some(target.trim())),
+        None => (line, None),
+    };
+
+    let open = decl.find('(').ok_or("Missing opening \"(\"")?;
+    if !decl.ends_with(')') {
+        return Err("Missing closing \")\"".to_string());
+    }
+
+    let head: Vec<&str> = decl[..open].split_whitespace().collect();
+    let (ret, name) = match head.as_slice() {
+        [name] => (BindType::Void, *name),
+        [ret, name] => (
+            BindType::from_str(ret).ok_or(format!("Unknown return type '{}'", ret))?,
+            *name,
+        ),
+        _ => return Err(format!("Invalid declaration '{}'", decl)),
+    };
+    if !is_identifier(name) {
+        return Err(format!("Invalid function name '{}'", name));
+    }
+    if matches!(ret, BindType::Str | BindType::Bubble) {
+        return Err(format!("Unsupported return type for '{}'", name));
+    }
+
+    let mut params = Vec::new();
+    let params_str = decl[open + 1..decl.len() - 1].trim();
+    if !params_str.is_empty() {
+        for param in params_str.split(',') {
+            let parts: Vec<&str> = param.split_whitespace().collect();
+            let (ty, param_name) = match parts.as_slice() {
+                [ty, param_name] => (*ty, *param_name),
+                _ => return Err(format!("Expected 'type name', found '{}'", param.trim())),
+            };
+            let ty = BindType::from_str(ty)
+                .filter(|ty| *ty != BindType::Void)
+                .ok_or(format!("Unknown parameter type '{}'", ty))?;
+            // the shim already uses these names
+            if !is_identifier(param_name)
+                || ["data", "out", "out_len", "result"].contains(&param_name)
+            {
+                return Err(format!("Invalid parameter name '{}'", param_name));
+            }
+            if params.iter().any(|(_, n)| n == param_name) {
+                return Err(format!("Parameter '{}' declared twice", param_name));
+            }
+            params.push((ty, param_name.to_string()));
+        }
+    }
+
+    if params.len() > 31 {
+        // srn takes a 5 bit argument
+        return Err(format!("'{}' has more than 31 parameters", name));
+    }
+
+    let target = match target {
+        Some(target) if is_identifier(target) => target.to_string(),
+        Some(target) => return Err(format!("Invalid target function '{}'", target)),
+        None => format!("{}_impl", name),
+    };
+
+    Ok(Binding {
+        name: name.to_string(),
+        target,
+        ret,
+        params,
+    })
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn c_params(binding: &Binding) -> String {
+    if binding.params.is_empty() {
+        return "void".to_string();
+    }
+    binding
+        .params
+        .iter()
+        .map(|(ty, name)| match ty {
+            BindType::Str => format!("{}{}", ty.c_type(), name),
+            _ => format!("{} {}", ty.c_type(), name),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// C header declaring the functions the shim calls, targets given with `=` are declared
+/// from the binding's signature, so it has to match the target's own declaration
+pub fn generate_header(bindings: &[Binding], guard: &str) -> String {
+    let guard = guard
+        .to_uppercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    let mut result = String::new();
+
+    let _ = writeln!(result, "// generated by awa5_rs, do not edit");
+    let _ = writeln!(result, "#ifndef {}_H", guard);
+    let _ = writeln!(result, "#define {}_H\n", guard);
+    let _ = writeln!(result, "#include <stddef.h>");
+    let _ = writeln!(result, "#include <stdint.h>\n");
+
+    let mut declared = Vec::new();
+    for binding in bindings {
+        // bindings may share a target
+        if declared.contains(&&binding.target) {
+            continue;
+        }
+        declared.push(&binding.target);
+        let _ = writeln!(
+            result,
+            "{} {}({});",
+            binding.ret.c_type(),
+            binding.target,
+            c_params(binding)
+        );
+    }
+    let _ = writeln!(result);
+
+    for binding in bindings {
+        let _ = writeln!(
+            result,
+            "void {}(const uint8_t *data, uint8_t **out, size_t *out_len);",
+            binding.name
+        );
+    }
+
+    let _ = writeln!(result, "\n#endif");
+    result
+}
+
+/// C shims unpacking `lib` arguments in the order the awasm macros push them
+pub fn generate_c(bindings: &[Binding], header: &str) -> String {
+    let mut result = String::new();
+
+    let _ = writeln!(result, "// generated by awa5_rs, do not edit");
+    let _ = writeln!(result, "#include \"{}\"\n", header);
+    let _ = writeln!(result, "#include <stdlib.h>");
+    let _ = writeln!(result, "#include <string.h>");
+
+    for binding in bindings {
+        let _ = writeln!(
+            result,
+            "\nvoid {}(const uint8_t *data, uint8_t **out, size_t *out_len)\n{{",
+            binding.name
+        );
+
+        for (ty, name) in &binding.params {
+            match ty {
+                BindType::Str => {
+                    let _ = writeln!(result, "    {}{};", ty.c_type(), name);
+                }
+                _ => {
+                    let _ = writeln!(result, "    {} {};", ty.c_type(), name);
+                }
+            }
+        }
+        if binding.params.is_empty() {
+            let _ = writeln!(result, "    (void)data;");
+        }
+        let _ = writeln!(result);
+
+        for (ty, name) in &binding.params {
+            match ty {
+                BindType::Str => {
+                    let _ = writeln!(result, "    {} = (const char *)data;", name);
+                    let _ = writeln!(result, "    data += strlen({}) + 1;\n", name);
+                }
+                _ => {
+                    let _ = writeln!(result, "    memcpy(&{}, data, sizeof({}));", name, name);
+                    let _ = writeln!(result, "    data += sizeof({});\n", name);
+                }
+            }
+        }
+
+        let args: Vec<&str> = binding.params.iter().map(|(_, n)| n.as_str()).collect();
+        let call = format!("{}({})", binding.target, args.join(", "));
+        match binding.ret {
+            BindType::Void => {
+                let _ = writeln!(result, "    {};", call);
+                let _ = writeln!(result, "    (void)out;");
+                let _ = writeln!(result, "    (void)out_len;");
+            }
+            ret => {
+                let _ = writeln!(result, "    {} result = {};\n", ret.c_type(), call);
+                let _ = writeln!(result, "    *out = (uint8_t *)malloc(sizeof(result));");
+                let _ = writeln!(result, "    memcpy(*out, &result, sizeof(result));");
+                let _ = writeln!(result, "    *out_len = sizeof(result);");
+            }
+        }
+        let _ = writeln!(result, "}}");
+    }
+
+    result
+}
+
+/// awasm `!def` macros pushing the function name and arguments, then calling `lib`
+pub fn generate_awasm(bindings: &[Binding]) -> String {
+    let mut result = String::new();
+
+    let _ = writeln!(result, "; generated by awa5_rs, do not edit");
+    let _ = writeln!(result, "!once");
+    let names: Vec<&str> = bindings.iter().map(|b| b.name.as_str()).collect();
+    if !names.is_empty() {
+        let _ = writeln!(result, "!export {}", names.join(", "));
+    }
+
+    for binding in bindings {
+        let args: Vec<&str> = binding.params.iter().map(|(_, n)| n.as_str()).collect();
+        if args.is_empty() {
+            let _ = writeln!(result, "\n!def {}", binding.name);
+        } else {
+            let _ = writeln!(result, "\n!def {}({})", binding.name, args.join(", "));
+        }
+
+        let _ = writeln!(result, "!str \"{}\"", binding.name);
+        for (ty, name) in &binding.params {
+            let _ = writeln!(result, "{}", ty.awasm_push(name));
+        }
+        if !binding.params.is_empty() {
+            let _ = writeln!(result, "srn {}", binding.params.len());
+            let _ = writeln!(result, "srn 2");
+        } else {
+            let _ = writeln!(result, "srn 1");
+        }
+        let _ = writeln!(result, "lib");
+        let _ = writeln!(result, "!end");
+    }
+
+    result
+}
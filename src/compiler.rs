@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io::Read;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::pipeline::{self, Format};
+use crate::sandbox::Policy;
+
+/// Builder for compiling programs to be run or converted
+///
+/// ```no_run
+/// use awa5_rs::Compiler;
+///
+/// let program = Compiler::new().include_path("lib").compile_file("main.awasm")?;
+/// program.run().map_err(|err| err.to_string())?;
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Compiler {
+    include_paths: Vec<String>,
+    library_paths: Vec<String>,
+    defines: Vec<(String, i64)>,
+    format: Option<Format>,
+    lenient: bool,
+    policy: Policy,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler::default()
+    }
+
+    /// Directory searched for awasm includes
+    pub fn include_path(mut self, path: &str) -> Self {
+        self.include_paths.push(path.to_string());
+        self
+    }
+
+    /// Directory searched for shared libraries of lib calls, /usr/local/lib when none
+    /// are given
+    pub fn library_path(mut self, path: &str) -> Self {
+        self.library_paths.push(path.to_string());
+        self
+    }
+
+    /// Constant assigned before parsing, as if by `!set name, value`
+    pub fn define(mut self, name: &str, value: i64) -> Self {
+        self.defines.push((name.to_string(), value));
+        self
+    }
+
+    /// Format of the source, otherwise taken from the file or awasm for strings
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Decode awatalk up to the first invalid token
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Sandbox policy for includes while compiling and for running the program
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn compile_file(&self, path: &str) -> Result<Program, String> {
+        let format = self
+            .format
+            .or(Format::of_file(path))
+            .ok_or(format!("Could not read {}", path))?;
+        let file = File::open(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+        self.compile(file, format, path)
+    }
+
+    pub fn compile_str(&self, source: &str) -> Result<Program, String> {
+        self.compile(source.as_bytes(), self.format.unwrap_or(Format::Awasm), "")
+    }
+
+    /// Compile a file and run it, returning the exit code the program set
+    pub fn run(&self, path: &str) -> Result<Option<i32>, String> {
+        self.compile_file(path)?
+            .run()
+            .map_err(|err| format!("Runtime error: {}", err))
+    }
+
+    fn compile(&self, reader: impl Read, format: Format, name: &str) -> Result<Program, String> {
+        let options = pipeline::Options {
+            include_paths: self
+                .policy
+                .filter_include_paths(&self.include_paths.join(";"))?,
+            include_dirs: self.policy.include_dirs.clone(),
+            lenient: self.lenient,
+            defines: self.defines.clone(),
+            ..Default::default()
+        };
+        let library_paths = if self.library_paths.is_empty() {
+            "/usr/local/lib".to_string()
+        } else {
+            self.library_paths.join(";")
+        };
+        Ok(Program {
+            object: pipeline::read_program(reader, format, name, &options)?,
+            library_paths,
+            policy: self.policy.clone(),
+        })
+    }
+}
+
+/// Compiled flat object with the settings to run it
+#[derive(Debug, Clone)]
+pub struct Program {
+    object: Vec<u8>,
+    library_paths: String,
+    policy: Policy,
+}
+
+impl Program {
+    /// Object code as `[op, arg]` pairs
+    pub fn object(&self) -> &[u8] {
+        &self.object
+    }
+
+    /// The program written as awasm, awatalk or an object
+    pub fn to_format(&self, format: Format) -> Vec<u8> {
+        pipeline::write_program(&self.object, format, &pipeline::Options::default())
+    }
+
+    /// Run the program, returning the exit code it set
+    pub fn run(&self) -> Result<Option<i32>, RuntimeError> {
+        self.run_with_args(Vec::new())
+    }
+
+    /// Run the program with `args` as its argv
+    pub fn run_with_args(&self, args: Vec<String>) -> Result<Option<i32>, RuntimeError> {
+        let mut interpreter = Interpreter::with_policy(&self.library_paths, self.policy.clone());
+        interpreter.set_args(args);
+        interpreter.run(self.object.clone())?;
+        Ok(interpreter.exit_code())
+    }
+}
@@ -1,14 +1,13 @@
-use core::panic;
-use std::{collections::HashMap, fs, path::PathBuf, ptr};
+use std::{fs, path::PathBuf};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{collections::HashMap, ptr};
 
 #[cfg(not(target_arch = "wasm32"))]
 use libloading::{Library, Symbol};
 
 use crate::{interpreter::Bubble, AWA_SCII};
 
-#[cfg(target_arch = "wasm32")]
-use crate::awa5_raylib;
-
 #[cfg(target_os = "windows")]
 const LIB_EXTENSION: &str = "dll";
 
@@ -23,61 +22,200 @@ const LIB_EXTENSION: &str = "dylib";
 #[cfg(target_arch = "wasm32")]
 const LIB_EXTENSION: &str = "wasm";
 
-pub fn parse_fn_name(data: &[u8]) -> String {
-    String::from_utf8(data.to_vec()).unwrap()
+/// Decode the function name given to `lib`, a string pushed with `!str` or a single char
+pub fn parse_fn_name(bubble: &Bubble) -> Result<String, String> {
+    let bytes = match bubble {
+        Bubble::Simple(val) => vec![*val as u8],
+        Bubble::Double(bubbles) => bubbles
+            .iter()
+            .rev()
+            .map(|bubble| match bubble {
+                Bubble::Simple(val) => Ok(*val as u8),
+                Bubble::Double(_) => Err("Expected a string of simple bubbles".to_string()),
+            })
+            .collect::<Result<_, _>>()?,
+    };
+    String::from_utf8(bytes).map_err(|_| "Expected a UTF-8 string".to_string())
+}
+
+/// A single `lib` argument, decoded from its type tag bubble
+#[derive(Debug, Clone, PartialEq)]
+pub enum LibArg {
+    /// `0x0` i32 or f32, raw little endian bytes
+    Word(Vec<u8>),
+    /// `0x1` awascii char
+    AwaChar(char),
+    /// `0x2` ascii char
+    Char(u8),
+    /// `0x3` awascii string
+    AwaStr(String),
+    /// `0x4` ascii string
+    Str(Vec<u8>),
+    /// `0x5` simple bubble value
+    Simple(i32),
+}
+
+impl LibArg {
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            LibArg::Word(bytes) => Some(i32::from_le_bytes(
+                <[u8; 4]>::try_from(bytes.as_slice()).ok()?,
+            )),
+            LibArg::Simple(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            LibArg::Word(bytes) => Some(f32::from_le_bytes(
+                <[u8; 4]>::try_from(bytes.as_slice()).ok()?,
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<String> {
+        match self {
+            LibArg::AwaStr(string) => Some(string.clone()),
+            LibArg::Str(bytes) => Some(String::from_utf8_lossy(bytes).to_string()),
+            LibArg::AwaChar(c) => Some(c.to_string()),
+            LibArg::Char(c) => Some((*c as char).to_string()),
+            _ => None,
+        }
+    }
+
+    /// Bytes passed to shared library functions, strings are null terminated
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            LibArg::Word(bytes) => bytes.clone(),
+            LibArg::AwaChar(c) => vec![*c as u8],
+            LibArg::Char(c) => vec![*c],
+            LibArg::AwaStr(string) => {
+                let mut bytes = string.as_bytes().to_vec();
+                bytes.push(b'\0');
+                bytes
+            }
+            LibArg::Str(string) => {
+                let mut bytes = string.clone();
+                bytes.push(b'\0');
+                bytes
+            }
+            LibArg::Simple(val) => val.to_le_bytes().to_vec(),
+        }
+    }
 }
 
-pub fn parse_fn_args(bubble: &Bubble) -> Vec<u8> {
+/// Bubbles returned by a lib function, pushed onto the abyss in order
+pub type LibResult = Result<Vec<Bubble>, String>;
+
+/// Host function registered with `Interpreter::register_fn`
+pub type NativeFn = Box<dyn FnMut(&[LibArg]) -> LibResult>;
+
+/// Decode the double bubble of `[type tag, value]` arguments given to `lib`
+pub fn decode_fn_args(bubble: &Bubble) -> Result<Vec<LibArg>, String> {
     let mut args = Vec::new();
 
-    let bubbles = bubble.get_bubbles();
-    for bubble in bubbles {
-        let bubbles = bubble.get_bubbles();
-        let arg_type = bubbles[0].get_val();
+    let bubbles = match bubble {
+        Bubble::Double(bubbles) => bubbles,
+        Bubble::Simple(_) => return Err("Expected a double bubble of arguments".to_string()),
+    };
+    for (index, bubble) in bubbles.iter().enumerate() {
+        let (arg_type, value) = match bubble {
+            Bubble::Double(bubbles) => match bubbles.as_slice() {
+                [Bubble::Simple(arg_type), value] => (*arg_type, value),
+                _ => {
+                    return Err(format!(
+                        "Expected a type tag and a value for argument {}",
+                        index
+                    ))
+                }
+            },
+            Bubble::Simple(_) => {
+                return Err(format!(
+                    "Expected a type tag and a value for argument {}",
+                    index
+                ))
+            }
+        };
+        let simple = |bubble: &Bubble| match bubble {
+            Bubble::Simple(val) => Ok(*val),
+            Bubble::Double(_) => Err(format!("Expected a simple bubble for argument {}", index)),
+        };
+        let values = || match value {
+            Bubble::Double(bubbles) => bubbles.iter().rev().map(simple).collect(),
+            Bubble::Simple(_) => Err(format!("Expected a string for argument {}", index)),
+        };
+        let awascii = |val: i32| {
+            usize::try_from(val)
+                .ok()
+                .and_then(|val| AWA_SCII.chars().nth(val))
+                .ok_or(format!(
+                    "{} is not an AWA-SCII character in argument {}",
+                    val, index
+                ))
+        };
 
         match arg_type {
             // i32 or f32
             0x0 => {
-                let byte_array = bubbles[1].to_u8_array();
-                args.extend_from_slice(&byte_array);
+                let bytes = match value {
+                    Bubble::Simple(val) => vec![*val as u8],
+                    Bubble::Double(bubbles) => bubbles
+                        .iter()
+                        .map(|bubble| simple(bubble).map(|val| val as u8))
+                        .collect::<Result<_, _>>()?,
+                };
+                args.push(LibArg::Word(bytes));
             }
             // awascii char
             0x1 => {
-                let char = AWA_SCII.chars().nth(bubbles[1].get_val() as usize).unwrap();
-                args.push(char as u8);
+                args.push(LibArg::AwaChar(awascii(simple(value)?)?));
             }
             // ascii char
             0x2 => {
-                args.push(bubbles[1].get_val() as u8);
+                args.push(LibArg::Char(simple(value)? as u8));
             }
             // awascii string
             0x3 => {
                 let mut new_string = String::new();
-                for b in bubbles[1].get_bubbles().iter().rev() {
-                    new_string.push(AWA_SCII.chars().nth(b.get_val() as usize).unwrap());
+                for val in values()? {
+                    new_string.push(awascii(val)?);
                 }
-                args.extend_from_slice(new_string.as_bytes());
-                args.push('\0' as u8)
+                args.push(LibArg::AwaStr(new_string));
             }
             // ascii string
             0x4 => {
-                for b in bubbles[1].get_bubbles().iter().rev() {
-                    args.push(b.get_val() as u8);
-                }
-                args.push('\0' as u8)
+                let values: Vec<i32> = values()?;
+                let new_string = values.into_iter().map(|val| val as u8).collect();
+                args.push(LibArg::Str(new_string));
             }
             // simple bubble value
             0x5 => {
-                let byte_array = bubbles[1].get_val().to_le_bytes();
-                args.extend_from_slice(&byte_array);
+                args.push(LibArg::Simple(simple(value)?));
             }
             _ => {
-                panic!("Invalid argument type provided: {}.", arg_type);
+                return Err(format!("Invalid type {} for argument {}", arg_type, index));
             }
         }
     }
 
-    args
+    Ok(args)
+}
+
+/// Bytes returned by shared library functions are pushed as simple bubbles
+pub fn bytes_to_bubbles(bytes: Vec<u8>) -> Vec<Bubble> {
+    bytes
+        .into_iter()
+        .map(|b| Bubble::Simple(b as i32))
+        .collect()
+}
+
+pub fn parse_fn_args(bubble: &Bubble) -> Result<Vec<u8>, String> {
+    Ok(decode_fn_args(bubble)?
+        .iter()
+        .flat_map(LibArg::to_bytes)
+        .collect())
 }
 
 pub fn get_shared_library_paths(lib_dirs: &[&str]) -> Vec<String> {
@@ -116,52 +254,12 @@ pub fn load_libs(lib_paths: &[&str]) -> HashMap<String, Library> {
     libs
 }
 
-#[cfg(target_arch = "wasm32")]
-pub type LibFnWithArgs = unsafe extern "C" fn(*const u8, *mut *mut u8, *mut usize);
-#[cfg(target_arch = "wasm32")]
-pub type LibFnNoArgs = unsafe extern "C" fn();
-
-#[cfg(target_arch = "wasm32")]
-pub enum LibFn {
-    WithArgs(LibFnWithArgs),
-    NoArgs(LibFnNoArgs),
-}
-
-#[cfg(target_arch = "wasm32")]
-pub fn load_libs(_lib_paths: &[&str]) -> HashMap<String, LibFn> {
-    let mut libs = HashMap::new();
-
-    libs.insert("initwindow".to_string(), LibFn::WithArgs(awa5_raylib::initwindow));
-    libs.insert("settargetfps".to_string(), LibFn::WithArgs(awa5_raylib::settargetfps));
-    libs.insert("clearbackground".to_string(), LibFn::WithArgs(awa5_raylib::clearbackground));
-    libs.insert("drawtext".to_string(), LibFn::WithArgs(awa5_raylib::drawtext));
-    libs.insert("iskeydown".to_string(), LibFn::WithArgs(awa5_raylib::iskeydown));
-    libs.insert("drawcircle".to_string(), LibFn::WithArgs(awa5_raylib::drawcircle));
-    libs.insert("setcameraposition".to_string(), LibFn::WithArgs(awa5_raylib::setcameraposition));
-    libs.insert("setcameratarget".to_string(), LibFn::WithArgs(awa5_raylib::setcameratarget));
-    libs.insert("setcameraup".to_string(), LibFn::WithArgs(awa5_raylib::setcameraup));
-    libs.insert("setcamerafovy".to_string(), LibFn::WithArgs(awa5_raylib::setcamerafovy));
-    libs.insert("setcameraprojection".to_string(), LibFn::WithArgs(awa5_raylib::setcameraprojection));
-    libs.insert("beginmode3d".to_string(), LibFn::WithArgs(awa5_raylib::beginmode3d));
-    libs.insert("drawcube".to_string(), LibFn::WithArgs(awa5_raylib::drawcube));
-    libs.insert("drawcubewires".to_string(), LibFn::WithArgs(awa5_raylib::drawcubewires));
-    libs.insert("drawgrid".to_string(), LibFn::WithArgs(awa5_raylib::drawgrid));
-    libs.insert("loadmodel".to_string(), LibFn::WithArgs(awa5_raylib::loadmodel));
-    libs.insert("unloadmodel".to_string(), LibFn::WithArgs(awa5_raylib::unloadmodel));
-    libs.insert("drawmodel".to_string(), LibFn::WithArgs(awa5_raylib::drawmodel));
-    libs.insert("drawmodelex".to_string(), LibFn::WithArgs(awa5_raylib::drawmodelex));
-    libs.insert("loadtexture".to_string(), LibFn::WithArgs(awa5_raylib::loadtexture));
-    libs.insert("setmaterialtexture".to_string(), LibFn::WithArgs(awa5_raylib::setmaterialtexture));
-    libs.insert("addfloat".to_string(), LibFn::WithArgs(awa5_raylib::addfloat));
-    libs.insert("BeginDrawing".to_string(), LibFn::NoArgs(awa5_raylib::BeginDrawing));
-    libs.insert("EndDrawing".to_string(), LibFn::NoArgs(awa5_raylib::EndDrawing));
-    libs.insert("EndMode3D".to_string(), LibFn::NoArgs(awa5_raylib::EndMode3D));
-
-    libs
-}
-
 #[cfg(not(target_arch = "wasm32"))]
-pub fn call_lib_fn(libs: &HashMap<String, Library>, fn_name: &str, args: Vec<u8>) -> Vec<u8> {
+pub fn call_lib_fn(
+    libs: &HashMap<String, Library>,
+    fn_name: &str,
+    args: Vec<u8>,
+) -> Option<Vec<u8>> {
     let mut buffer: *mut u8 = ptr::null_mut();
     let mut buffer_len: usize = 0;
 
@@ -174,41 +272,12 @@ pub fn call_lib_fn(libs: &HashMap<String, Library>, fn_name: &str, args: Vec<u8>
             {
                 f(args.as_ptr(), &mut buffer, &mut buffer_len);
                 if !buffer.is_null() {
-                    return Vec::from_raw_parts(buffer, buffer_len, buffer_len);
+                    return Some(Vec::from_raw_parts(buffer, buffer_len, buffer_len));
                 }
-                return vec![];
+                return Some(vec![]);
             }
         }
     }
 
-    panic!("Function not found: {}", fn_name);
-}
-
-#[cfg(target_arch = "wasm32")]
-pub fn call_lib_fn(libs: &HashMap<String, LibFn>, fn_name: &str, args: Vec<u8>) -> Vec<u8> {
-    match libs.get(fn_name) {
-        Some(LibFn::WithArgs(lib_fn)) => {
-            let mut buffer: *mut u8 = ptr::null_mut();
-            let mut buffer_len: usize = 0;
-
-            unsafe {
-                lib_fn(args.as_ptr(), &mut buffer, &mut buffer_len);
-
-                if !buffer.is_null() {
-                    return Vec::from_raw_parts(buffer, buffer_len, buffer_len);
-                }
-                return vec![];
-            }
-        }
-        Some(LibFn::NoArgs(lib_fn)) => {
-            unsafe {
-                lib_fn();
-            }
-            Vec::new()
-        }
-        None => {
-            eprintln!("Function not found in library");
-            Vec::new()
-        }
-    }
+    None
 }
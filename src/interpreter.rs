@@ -1,16 +1,72 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fmt,
+    io::{self, BufRead, BufReader},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use libloading::Library;
 
 use crate::{
-    dynlib::{self, call_lib_fn},
+    assembler,
+    dynlib::{self, LibArg, LibResult, NativeFn},
+    sandbox::{Limits, Policy},
+    stdlib::{self, StdConfig},
     Awatism, AWA_SCII,
 };
 
+#[cfg(target_arch = "wasm32")]
+use crate::awa5_raylib;
+
 #[derive(Debug)]
 struct Instruction {
     op: u8,
     arg: u8,
 }
 
+/// Instructions of a running program, read from `source` only as far as execution needs
+struct Code<'a> {
+    instructions: Vec<Instruction>,
+    /// Position of the last `lbl` of each number read so far
+    label_map: HashMap<u8, usize>,
+    source: Box<dyn Iterator<Item = Result<(u8, u8), RuntimeError>> + 'a>,
+}
+
+impl<'a> Code<'a> {
+    fn new(source: impl Iterator<Item = Result<(u8, u8), RuntimeError>> + 'a) -> Self {
+        Code {
+            instructions: Vec::new(),
+            label_map: HashMap::new(),
+            source: Box::new(source.fuse()),
+        }
+    }
+
+    /// Read instructions up to the one at `index`, false when the program ends before it
+    fn load(&mut self, index: usize) -> Result<bool, RuntimeError> {
+        while self.instructions.len() <= index {
+            let (op, arg) = match self.source.next() {
+                Some(instruction) => instruction?,
+                None => return Ok(false),
+            };
+            if let Some(Awatism::Lbl(arg)) = Awatism::from_u8(op, arg) {
+                self.label_map.insert(arg, self.instructions.len());
+            }
+            self.instructions.push(Instruction { op, arg });
+        }
+        Ok(true)
+    }
+
+    /// Position of the `lbl` a `jmp` goes to, the last one wins so the rest of the
+    /// program is read first
+    fn label(&mut self, arg: u8) -> Result<usize, RuntimeError> {
+        self.load(usize::MAX)?;
+        Ok(*self.label_map.get(&arg).unwrap())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Bubble {
     Simple(i32),
@@ -51,25 +107,58 @@ impl Bubble {
         }
         vec
     }
+
+    /// Number of bubbles including nested ones, and nesting depth
+    pub fn measure(&self) -> (usize, usize) {
+        match self {
+            Bubble::Simple(_) => (1, 0),
+            Bubble::Double(bubbles) => {
+                let mut count = 1;
+                let mut depth = 0;
+                for bubble in bubbles {
+                    let (c, d) = bubble.measure();
+                    count += c;
+                    depth = depth.max(d);
+                }
+                (count, depth + 1)
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 struct BubbleAbyss {
     bubbles: Vec<Bubble>,
+    // bubble count and depth of each bubble, kept alongside for resource limits
+    measures: Vec<(usize, usize)>,
+    total: usize,
 }
 
 impl BubbleAbyss {
     pub fn new() -> Self {
         BubbleAbyss {
             bubbles: Vec::new(),
+            measures: Vec::new(),
+            total: 0,
         }
     }
 
     pub fn push(&mut self, bubble: Bubble) {
-        self.bubbles.push(bubble);
+        let len = self.bubbles.len();
+        self.insert(len, bubble);
+    }
+
+    pub fn insert(&mut self, index: usize, bubble: Bubble) {
+        let measure = bubble.measure();
+        self.total += measure.0;
+        self.measures.insert(index, measure);
+        self.bubbles.insert(index, bubble);
     }
 
     pub fn pop(&mut self) -> Option<Bubble> {
+        if let Some((count, _)) = self.measures.pop() {
+            self.total -= count;
+        }
         self.bubbles.pop()
     }
 
@@ -91,322 +180,710 @@ impl BubbleAbyss {
     }
 }
 
-pub fn interpet_object(object_vec: Vec<u8>, path: &str) {
-    let mut label_map: HashMap<u8, usize> = HashMap::new();
-    let mut instructions: Vec<Instruction> = Vec::new();
+#[derive(Debug)]
+pub enum RuntimeError {
+    LibDisabled,
+    LibNotAllowed(String),
+    FunctionNotFound(String),
+    FunctionFailed(String, String),
+    InstructionLimit(usize),
+    AbyssLimit(usize),
+    BubbleSizeLimit(usize),
+    DepthLimit(usize),
+    BubbleCountLimit(usize),
+    Timeout(Duration),
+    UnknownSyscall(i32),
+    /// `sys` found no simple bubble with a service number on top of the abyss
+    MissingSyscallService,
+    SyscallFailed(&'static str, String),
+    /// `r3d` read a line that does not start with a number, empty at the end of input
+    NotANumber(String),
+    /// `jro` would continue at an instruction outside the program
+    JumpOutOfRange(i64),
+    /// Arguments of a `lib` call that cannot be decoded
+    InvalidLibArgs(String, String),
+    /// Function name of a `lib` call that cannot be decoded
+    InvalidLibName(String),
+    /// The program could not be decoded up to the instruction execution reached
+    InvalidProgram(String),
+}
 
-    let mut bubble_abyss = BubbleAbyss::new();
+impl RuntimeError {
+    /// Whether the error was caused by exceeding a resource limit
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            RuntimeError::InstructionLimit(_)
+                | RuntimeError::AbyssLimit(_)
+                | RuntimeError::BubbleSizeLimit(_)
+                | RuntimeError::DepthLimit(_)
+                | RuntimeError::BubbleCountLimit(_)
+                | RuntimeError::Timeout(_)
+        )
+    }
+}
 
-    let paths: Vec<&str> = path.split(';').collect();
-    let lib_paths = dynlib::get_shared_library_paths(&paths);
-    let lib_paths_str: Vec<&str> = lib_paths.iter().map(AsRef::as_ref).collect();
-    let libs = dynlib::load_libs(&lib_paths_str);
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::LibDisabled => write!(f, "lib calls are disabled"),
+            RuntimeError::LibNotAllowed(fn_name) => {
+                write!(f, "lib function '{}' is not allowed", fn_name)
+            }
+            RuntimeError::FunctionNotFound(fn_name) => {
+                write!(f, "Function not found: {}", fn_name)
+            }
+            RuntimeError::FunctionFailed(fn_name, err) => {
+                write!(f, "Function {} failed: {}", fn_name, err)
+            }
+            RuntimeError::InstructionLimit(max) => {
+                write!(f, "Exceeded instruction limit of {}", max)
+            }
+            RuntimeError::AbyssLimit(max) => write!(f, "Exceeded abyss limit of {} bubbles", max),
+            RuntimeError::BubbleSizeLimit(max) => {
+                write!(f, "Exceeded double bubble limit of {} bubbles", max)
+            }
+            RuntimeError::DepthLimit(max) => write!(f, "Exceeded nesting depth limit of {}", max),
+            RuntimeError::BubbleCountLimit(max) => {
+                write!(f, "Exceeded total bubble limit of {}", max)
+            }
+            RuntimeError::Timeout(timeout) => {
+                write!(f, "Exceeded time limit of {} ms", timeout.as_millis())
+            }
+            RuntimeError::UnknownSyscall(service) => write!(f, "Unknown syscall {}", service),
+            RuntimeError::MissingSyscallService => {
+                write!(f, "sys expected a service number on top of the abyss")
+            }
+            RuntimeError::SyscallFailed(service, err) => {
+                write!(f, "Syscall {} failed: {}", service, err)
+            }
+            RuntimeError::NotANumber(line) if line.is_empty() => {
+                write!(f, "r3d reached the end of the input")
+            }
+            RuntimeError::NotANumber(line) => write!(f, "r3d expected a number, found '{}'", line),
+            RuntimeError::InvalidLibArgs(fn_name, err) => {
+                write!(f, "Invalid arguments to {}: {}", fn_name, err)
+            }
+            RuntimeError::InvalidLibName(err) => write!(f, "Invalid lib function name: {}", err),
+            RuntimeError::InvalidProgram(err) => write!(f, "{}", err),
+            RuntimeError::JumpOutOfRange(target) => {
+                write!(f, "jro to instruction {} is outside the program", target)
+            }
+        }
+    }
+}
 
-    // create label map
-    for (index, chunk) in object_vec.chunks_exact(2).enumerate() {
-        let op = chunk[0];
-        let arg = chunk[1];
+impl std::error::Error for RuntimeError {}
+
+pub struct Interpreter {
+    #[cfg(not(target_arch = "wasm32"))]
+    libs: HashMap<String, Library>,
+    native_fns: HashMap<String, NativeFn>,
+    policy: Policy,
+    /// Arguments passed to the program, read with the argv syscalls
+    args: Vec<String>,
+    /// Set by the exit syscall
+    exit_code: Option<i32>,
+    /// Lines read by `red` and `r3d`
+    input: Box<dyn BufRead>,
+    /// Options read by the built-in `std` lib functions when they are called
+    std_config: Rc<RefCell<StdConfig>>,
+}
 
-        let instruction = Instruction { op, arg };
-        instructions.push(instruction);
+impl Interpreter {
+    /// Create an interpreter, loading shared libraries from search paths separated by ';'
+    pub fn new(path: &str) -> Self {
+        Interpreter::with_policy(path, Policy::default())
+    }
 
-        match Awatism::from_u8(op, arg).unwrap() {
-            Awatism::Lbl(arg) => {
-                label_map.insert(arg, index);
-            }
-            _ => {}
-        }
+    /// Create an interpreter restricted by a sandbox policy, libraries that are not
+    /// allowed are never loaded
+    pub fn with_policy(path: &str, policy: Policy) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let libs = {
+            let paths: Vec<&str> = path.split(';').collect();
+            let lib_paths = dynlib::get_shared_library_paths(&paths);
+            let lib_paths_str: Vec<&str> = lib_paths
+                .iter()
+                .map(AsRef::as_ref)
+                .filter(|lib_path| !policy.disable_lib && policy.allows_lib(lib_path))
+                .collect();
+            dynlib::load_libs(&lib_paths_str)
+        };
+        #[cfg(target_arch = "wasm32")]
+        let _ = path;
+
+        let mut interpreter = Interpreter {
+            #[cfg(not(target_arch = "wasm32"))]
+            libs,
+            native_fns: HashMap::new(),
+            policy,
+            args: vec![],
+            exit_code: None,
+            input: Box::new(BufReader::new(io::stdin())),
+            std_config: Rc::new(RefCell::new(StdConfig::default())),
+        };
+        let std_config = interpreter.std_config.clone();
+        stdlib::register(&mut interpreter, &std_config);
+
+        #[cfg(target_arch = "wasm32")]
+        awa5_raylib::register(&mut interpreter);
+
+        interpreter
+    }
+
+    /// Set the options of the built-in `std` lib functions
+    pub fn set_std_config(&mut self, config: &StdConfig) {
+        *self.std_config.borrow_mut() = config.clone();
+    }
+
+    /// Register a host function callable with `lib`, taking precedence over shared libraries
+    pub fn register_fn<F>(&mut self, fn_name: &str, lib_fn: F)
+    where
+        F: FnMut(&[LibArg]) -> LibResult + 'static,
+    {
+        self.native_fns
+            .insert(fn_name.to_string(), Box::new(lib_fn));
+    }
+
+    /// Set the arguments the program reads with the argv syscalls
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
     }
 
-    let mut index = 0;
-    while index < instructions.len() {
-        let instruction = &instructions[index];
-        let op = instruction.op;
-        let arg = instruction.arg;
+    /// Read the lines of `red` and `r3d` from `input` instead of stdin
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = input;
+    }
+
+    /// Exit code given to the exit syscall by the last run
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
 
-        match Awatism::from_u8(op, arg).unwrap() {
-            Awatism::Nop => {}
-            Awatism::Prn => {
-                let bubble = bubble_abyss.top().unwrap().clone();
-                print_bubble(&mut bubble_abyss, &bubble, false, false);
+    /// Run the host service numbered by the top bubble, its arguments are the bubbles
+    /// below. Returns true when the program exits
+    ///
+    /// | Service | Arguments | Pushes |
+    /// | --- | --- | --- |
+    /// | 0 exit | exit code | |
+    /// | 1 argc | | argument count |
+    /// | 2 argv | index | ascii string |
+    /// | 3 argv AWA-SCII | index | AWA-SCII string |
+    /// | 4 env | ascii string name | ascii string, empty when unset |
+    /// | 5 time | | seconds since the unix epoch |
+    fn syscall(&mut self, bubble_abyss: &mut BubbleAbyss) -> Result<bool, RuntimeError> {
+        let service = match bubble_abyss.pop() {
+            Some(Bubble::Simple(service)) => service,
+            _ => return Err(RuntimeError::MissingSyscallService),
+        };
+        let name = match service {
+            0 => "exit",
+            1 => "argc",
+            2 | 3 => "argv",
+            4 => "env",
+            5 => "time",
+            _ => return Err(RuntimeError::UnknownSyscall(service)),
+        };
+        let fail = |err: String| RuntimeError::SyscallFailed(name, err);
+
+        match service {
+            0 => {
+                self.exit_code = Some(pop_simple(bubble_abyss).map_err(fail)?);
+                return Ok(true);
+            }
+            1 => bubble_abyss.push(Bubble::Simple(self.args.len() as i32)),
+            2 | 3 => {
+                let index = pop_simple(bubble_abyss).map_err(fail)?;
+                let arg = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| self.args.get(index))
+                    .ok_or_else(|| fail(format!("No argument {}", index)))?;
+                let bubble = if service == 2 {
+                    stdlib::ascii_bubble(arg)
+                } else {
+                    stdlib::awascii_bubble(arg).map_err(fail)?
+                };
+                bubble_abyss.push(bubble);
+            }
+            4 => {
+                let key = match bubble_abyss.pop() {
+                    Some(Bubble::Double(bubbles)) => bubbles
+                        .iter()
+                        .rev()
+                        .map(|bubble| bubble.get_val() as u8 as char)
+                        .collect::<String>(),
+                    _ => return Err(fail("Expected a string".to_string())),
+                };
+                let value = match self.policy.disable_env {
+                    true => None,
+                    false => env::var(&key).ok(),
+                };
+                bubble_abyss.push(stdlib::ascii_bubble(&value.unwrap_or_default()));
             }
-            Awatism::Pr1 => {
-                let bubble = bubble_abyss.top().unwrap().clone();
-                print_bubble(&mut bubble_abyss, &bubble, true, false);
+            _ => {
+                let seconds = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs());
+                // would not fit a simple bubble after 2038
+                bubble_abyss.push(stdlib::i64_bubble(seconds as i64));
             }
-            Awatism::Red => {
-                let mut buffer = String::new();
-                let _ = std::io::stdin().read_line(&mut buffer);
+        }
+        Ok(false)
+    }
 
-                let mut bubbles = Vec::new();
-                for c in buffer.chars() {
-                    if !AWA_SCII.contains(c) {
-                        break;
-                    }
-                    bubbles.insert(
-                        0,
-                        Bubble::Simple(AWA_SCII.chars().position(|f| f == c).unwrap() as i32),
-                    );
-                }
+    fn call_fn(
+        &mut self,
+        fn_name: &str,
+        args: Option<&Bubble>,
+    ) -> Result<Vec<Bubble>, RuntimeError> {
+        if self.policy.disable_lib {
+            return Err(RuntimeError::LibDisabled);
+        }
+        if !self.policy.allows_fn(fn_name)
+            || (fn_name.starts_with("std.") && !self.policy.allows_lib("std"))
+        {
+            return Err(RuntimeError::LibNotAllowed(fn_name.to_string()));
+        }
+
+        let invalid_args = |err: String| RuntimeError::InvalidLibArgs(fn_name.to_string(), err);
+        if let Some(lib_fn) = self.native_fns.get_mut(fn_name) {
+            let args = match args {
+                Some(bubble) => dynlib::decode_fn_args(bubble).map_err(invalid_args)?,
+                None => vec![],
+            };
+            return lib_fn(&args)
+                .map_err(|err| RuntimeError::FunctionFailed(fn_name.to_string(), err));
+        }
 
-                bubble_abyss.push(Bubble::Double(bubbles));
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let args = match args {
+                Some(bubble) => dynlib::parse_fn_args(bubble).map_err(invalid_args)?,
+                None => vec![],
+            };
+            match dynlib::call_lib_fn(&self.libs, fn_name, args) {
+                Some(buffer) => Ok(dynlib::bytes_to_bubbles(buffer)),
+                None => Err(RuntimeError::FunctionNotFound(fn_name.to_string())),
             }
-            Awatism::R3d => {
-                let mut buffer = String::new();
-                let _ = std::io::stdin().read_line(&mut buffer);
+        }
 
-                let mut negative = 1;
+        #[cfg(target_arch = "wasm32")]
+        Err(RuntimeError::FunctionNotFound(fn_name.to_string()))
+    }
 
-                let mut num = String::new();
-                for (i, c) in buffer.chars().enumerate() {
-                    if i == 0 && c == '-' {
-                        negative = -1;
-                        continue;
-                    }
-                    if !c.is_ascii_digit() {
-                        break;
-                    }
-                    num += &c.to_string();
-                }
+    /// Replace the resource limits from the policy
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.policy.limits = limits;
+    }
 
-                bubble_abyss.push(Bubble::Simple(negative * num.parse::<i32>().unwrap()));
+    fn check_limits(&self, bubble_abyss: &BubbleAbyss) -> Result<(), RuntimeError> {
+        let limits = &self.policy.limits;
+        if let Some(max) = limits.max_abyss {
+            if bubble_abyss.bubbles.len() > max {
+                return Err(RuntimeError::AbyssLimit(max));
             }
-            Awatism::Blo(arg) => {
-                bubble_abyss.push(Bubble::Simple((arg as i8) as i32));
+        }
+        if let Some(max) = limits.max_bubbles {
+            if bubble_abyss.total > max {
+                return Err(RuntimeError::BubbleCountLimit(max));
             }
-            Awatism::Sbm(arg) => {
-                let bubble = bubble_abyss.pop().unwrap();
-                if arg == 0 {
-                    bubble_abyss.bubbles.insert(0, bubble);
-                } else {
-                    bubble_abyss
-                        .bubbles
-                        .insert(bubble_abyss.bubbles.len() - arg as usize, bubble);
+        }
+        // only the top bubble can have grown since the last instruction
+        if let Some(max) = limits.max_bubble_size {
+            if let Some(Bubble::Double(bubbles)) = bubble_abyss.top() {
+                if bubbles.len() > max {
+                    return Err(RuntimeError::BubbleSizeLimit(max));
                 }
             }
-            Awatism::Pop => {
-                let bubble = bubble_abyss.pop().unwrap();
-                match bubble {
-                    Bubble::Double(mut bubbles) => {
-                        let removed = bubbles.remove(0);
-                        bubble_abyss.push(removed);
-                    }
-                    _ => {}
+        }
+        if let Some(max) = limits.max_depth {
+            if let Some((_, depth)) = bubble_abyss.measures.last() {
+                if *depth > max {
+                    return Err(RuntimeError::DepthLimit(max));
                 }
             }
-            Awatism::Dpl => bubble_abyss.push(bubble_abyss.top().unwrap().clone()),
-            Awatism::Srn(arg) => {
-                let mut bubbles = Vec::new();
-                for _ in 0..arg {
-                    bubbles.insert(0, bubble_abyss.pop().unwrap().clone())
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self, object_vec: Vec<u8>) -> Result<(), RuntimeError> {
+        let pairs = object_vec
+            .chunks_exact(2)
+            .map(|pair| Ok((pair[0], pair[1])));
+        self.run_code(Code::new(pairs))
+    }
+
+    /// Run instructions while they are decoded, like awatalk read with
+    /// `parser::awatalk::Instructions`, so a program starts before all of it is read
+    ///
+    /// Only a `jmp`, a forward `jro` or `call`, and a comparison skipping a `jro` read
+    /// ahead. A decode error stops the program with `RuntimeError::InvalidProgram` once
+    /// execution reaches it
+    pub fn run_instructions<E: fmt::Display>(
+        &mut self,
+        instructions: impl Iterator<Item = Result<crate::Instruction, E>>,
+    ) -> Result<(), RuntimeError> {
+        let pairs = instructions.map(|instruction| match instruction {
+            Ok(instruction) => match assembler::assemble_awatism(&instruction)[..] {
+                [op, arg] => Ok((op, arg)),
+                _ => Err(RuntimeError::InvalidProgram(format!(
+                    "{:?} is not an object instruction",
+                    instruction.awatism
+                ))),
+            },
+            Err(err) => Err(RuntimeError::InvalidProgram(err.to_string())),
+        });
+        self.run_code(Code::new(pairs))
+    }
+
+    fn run_code(&mut self, mut code: Code) -> Result<(), RuntimeError> {
+        let mut bubble_abyss = BubbleAbyss::new();
+
+        self.exit_code = None;
+        let start = Instant::now();
+        let mut executed: usize = 0;
+        let mut index = 0;
+        while code.load(index)? {
+            if let Some(max) = self.policy.limits.max_instructions {
+                if executed >= max {
+                    return Err(RuntimeError::InstructionLimit(max));
                 }
-                bubble_abyss.push(Bubble::Double(bubbles))
             }
-            Awatism::Mrg => {
-                let bubble1 = bubble_abyss.pop().unwrap();
-                let bubble2 = bubble_abyss.pop().unwrap();
-
-                let is_b1_double = bubble1.is_double();
-                let is_b2_double = bubble2.is_double();
-
-                if !is_b1_double && !is_b2_double {
-                    let mut vec = Vec::new();
-                    vec.push(bubble2);
-                    vec.push(bubble1);
-                    bubble_abyss.push(Bubble::Double(vec));
-                } else if is_b1_double && !is_b2_double {
-                    let mut bubbles = bubble1.get_bubbles().clone();
-                    bubbles.insert(0, bubble2);
-                    bubble_abyss.push(Bubble::Double(bubbles));
-                } else if !is_b1_double && is_b2_double {
-                    let mut bubbles = bubble2.get_bubbles().clone();
-                    bubbles.insert(0, bubble1);
+            if let Some(timeout) = self.policy.limits.timeout {
+                // reading the clock every instruction is needlessly slow
+                if executed.is_multiple_of(256) && start.elapsed() > timeout {
+                    return Err(RuntimeError::Timeout(timeout));
+                }
+            }
+            executed += 1;
+
+            let instruction = &code.instructions[index];
+            let op = instruction.op;
+            let arg = instruction.arg;
+
+            match Awatism::from_u8(op, arg).unwrap() {
+                Awatism::Nop => {}
+                Awatism::Prn => {
+                    let bubble = bubble_abyss.top().unwrap().clone();
+                    print_bubble(&mut bubble_abyss, &bubble, false, false);
+                }
+                Awatism::Pr1 => {
+                    let bubble = bubble_abyss.top().unwrap().clone();
+                    print_bubble(&mut bubble_abyss, &bubble, true, false);
+                }
+                Awatism::Red => {
+                    let mut buffer = String::new();
+                    let _ = self.input.read_line(&mut buffer);
+
+                    let mut bubbles = Vec::new();
+                    for c in buffer.chars() {
+                        if !AWA_SCII.contains(c) {
+                            break;
+                        }
+                        bubbles.insert(
+                            0,
+                            Bubble::Simple(AWA_SCII.chars().position(|f| f == c).unwrap() as i32),
+                        );
+                    }
+
                     bubble_abyss.push(Bubble::Double(bubbles));
-                } else {
-                    let mut bubbles1 = bubble1.get_bubbles().clone();
-                    let mut bubbles2 = bubble2.get_bubbles().clone();
-                    while bubbles1.len() > 0 {
-                        bubbles2.push(bubbles1.remove(0));
+                }
+                Awatism::R3d => {
+                    let mut buffer = String::new();
+                    let _ = self.input.read_line(&mut buffer);
+
+                    let mut negative = 1;
+
+                    let mut num = String::new();
+                    for (i, c) in buffer.chars().enumerate() {
+                        if i == 0 && c == '-' {
+                            negative = -1;
+                            continue;
+                        }
+                        if !c.is_ascii_digit() {
+                            break;
+                        }
+                        num += &c.to_string();
                     }
-                    bubble_abyss.push(Bubble::Double(bubbles2));
+
+                    let num = num.parse::<i32>().map_err(|_| {
+                        RuntimeError::NotANumber(buffer.trim_end_matches(['\r', '\n']).to_string())
+                    })?;
+                    bubble_abyss.push(Bubble::Simple(negative * num));
                 }
-            }
-            Awatism::Add => {
-                let bubble1 = bubble_abyss.pop().unwrap();
-                let bubble2 = bubble_abyss.pop().unwrap();
-                let result = operate_bubbles(&add_bubbles, &bubble1, &bubble2);
-                bubble_abyss.push(result);
-            }
-            Awatism::Sub => {
-                let bubble1 = bubble_abyss.pop().unwrap();
-                let bubble2 = bubble_abyss.pop().unwrap();
-                let result = operate_bubbles(&sub_bubbles, &bubble1, &bubble2);
-                bubble_abyss.push(result);
-            }
-            Awatism::Mul => {
-                let bubble1 = bubble_abyss.pop().unwrap();
-                let bubble2 = bubble_abyss.pop().unwrap();
-                let result = operate_bubbles(&mul_bubbles, &bubble1, &bubble2);
-                bubble_abyss.push(result);
-            }
-            Awatism::Div => {
-                let bubble1 = bubble_abyss.pop().unwrap();
-                let bubble2 = bubble_abyss.pop().unwrap();
-                let result = operate_bubbles(&div_bubbles, &bubble1, &bubble2);
-                bubble_abyss.push(result);
-            }
-            Awatism::Cnt => {
-                let bubble = bubble_abyss.top().unwrap();
-                match bubble {
-                    Bubble::Simple(_) => bubble_abyss.push(Bubble::Simple(0)),
-                    Bubble::Double(_) => {
-                        bubble_abyss.push(Bubble::Simple(bubble.get_bubbles().len() as i32))
+                Awatism::Blo(arg) => {
+                    bubble_abyss.push(Bubble::Simple((arg as i8) as i32));
+                }
+                Awatism::Sbm(arg) => {
+                    let bubble = bubble_abyss.pop().unwrap();
+                    if arg == 0 {
+                        bubble_abyss.insert(0, bubble);
+                    } else {
+                        bubble_abyss.insert(bubble_abyss.bubbles.len() - arg as usize, bubble);
                     }
                 }
-            }
-            Awatism::Lbl(_arg) => {}
-            Awatism::Jmp(arg) => {
-                let jump_position = label_map.get(&arg).unwrap();
-                index = *jump_position;
-            }
-            Awatism::Eql => {
-                let top = bubble_abyss.top().unwrap();
-                let before_top = bubble_abyss.before_top().unwrap();
-
-                if !top.is_double()
-                    && !before_top.is_double()
-                    && top.get_val() == before_top.get_val()
-                {
-                    // execute next line
-                } else {
-                    if index + 6 < instructions.len() {
-                        match Awatism::from_u8(instructions[index + 6].op, 0x00).unwrap() {
-                            Awatism::JmpRel => {
-                                // treat a relative jump as single instruction
-                                index += 6;
-                            }
-                            _ => {
-                                index += 1;
-                            }
+                Awatism::Pop => {
+                    let bubble = bubble_abyss.pop().unwrap();
+                    match bubble {
+                        Bubble::Double(mut bubbles) => {
+                            let removed = bubbles.remove(0);
+                            bubble_abyss.push(removed);
                         }
+                        _ => {}
+                    }
+                }
+                Awatism::Dpl => bubble_abyss.push(bubble_abyss.top().unwrap().clone()),
+                Awatism::Srn(arg) => {
+                    let mut bubbles = Vec::new();
+                    for _ in 0..arg {
+                        bubbles.insert(0, bubble_abyss.pop().unwrap().clone())
+                    }
+                    bubble_abyss.push(Bubble::Double(bubbles))
+                }
+                Awatism::Mrg => {
+                    let bubble1 = bubble_abyss.pop().unwrap();
+                    let bubble2 = bubble_abyss.pop().unwrap();
+
+                    let is_b1_double = bubble1.is_double();
+                    let is_b2_double = bubble2.is_double();
+
+                    if !is_b1_double && !is_b2_double {
+                        let mut vec = Vec::new();
+                        vec.push(bubble2);
+                        vec.push(bubble1);
+                        bubble_abyss.push(Bubble::Double(vec));
+                    } else if is_b1_double && !is_b2_double {
+                        let mut bubbles = bubble1.get_bubbles().clone();
+                        bubbles.insert(0, bubble2);
+                        bubble_abyss.push(Bubble::Double(bubbles));
+                    } else if !is_b1_double && is_b2_double {
+                        let mut bubbles = bubble2.get_bubbles().clone();
+                        bubbles.insert(0, bubble1);
+                        bubble_abyss.push(Bubble::Double(bubbles));
                     } else {
-                        index += 1;
+                        let mut bubbles1 = bubble1.get_bubbles().clone();
+                        let mut bubbles2 = bubble2.get_bubbles().clone();
+                        while bubbles1.len() > 0 {
+                            bubbles2.push(bubbles1.remove(0));
+                        }
+                        bubble_abyss.push(Bubble::Double(bubbles2));
                     }
                 }
-            }
-            Awatism::Lss => {
-                let top = bubble_abyss.top().unwrap();
-                let before_top = bubble_abyss.before_top().unwrap();
-
-                if !top.is_double()
-                    && !before_top.is_double()
-                    && top.get_val() < before_top.get_val()
-                {
-                    // execute next line
-                } else {
-                    index += 1;
+                Awatism::Add => {
+                    let bubble1 = bubble_abyss.pop().unwrap();
+                    let bubble2 = bubble_abyss.pop().unwrap();
+                    let result = operate_bubbles(&add_bubbles, &bubble1, &bubble2);
+                    bubble_abyss.push(result);
                 }
-            }
-            Awatism::Gr8 => {
-                let top = bubble_abyss.top().unwrap();
-                let before_top = bubble_abyss.before_top().unwrap();
-
-                if !top.is_double()
-                    && !before_top.is_double()
-                    && top.get_val() > before_top.get_val()
-                {
-                    // execute next line
-                } else {
-                    index += 1;
+                Awatism::Sub => {
+                    let bubble1 = bubble_abyss.pop().unwrap();
+                    let bubble2 = bubble_abyss.pop().unwrap();
+                    let result = operate_bubbles(&sub_bubbles, &bubble1, &bubble2);
+                    bubble_abyss.push(result);
                 }
-            }
-            Awatism::Lib => {
-                let top = bubble_abyss.pop().unwrap();
-                match top {
-                    Bubble::Simple(_) => {}
-                    Bubble::Double(bubbles) => {
-                        let mut fn_name = bubbles[0].to_u8_array();
-                        fn_name.reverse();
-                        let fn_name = dynlib::parse_fn_name(&fn_name);
-
-                        if bubbles.len() == 1 {
-                            let buffer = call_lib_fn(&libs, &fn_name, vec![]);
-                            for b in buffer {
-                                bubble_abyss.push(Bubble::Simple(b as i32));
-                            }
-                        } else {
-                            let fn_args = dynlib::parse_fn_args(&bubbles[1]);
-                            let buffer = call_lib_fn(&libs, &fn_name, fn_args);
-                            for b in buffer {
-                                bubble_abyss.push(Bubble::Simple(b as i32));
-                            }
+                Awatism::Mul => {
+                    let bubble1 = bubble_abyss.pop().unwrap();
+                    let bubble2 = bubble_abyss.pop().unwrap();
+                    let result = operate_bubbles(&mul_bubbles, &bubble1, &bubble2);
+                    bubble_abyss.push(result);
+                }
+                Awatism::Div => {
+                    let bubble1 = bubble_abyss.pop().unwrap();
+                    let bubble2 = bubble_abyss.pop().unwrap();
+                    let result = operate_bubbles(&div_bubbles, &bubble1, &bubble2);
+                    bubble_abyss.push(result);
+                }
+                Awatism::Cnt => {
+                    let bubble = bubble_abyss.top().unwrap();
+                    match bubble {
+                        Bubble::Simple(_) => bubble_abyss.push(Bubble::Simple(0)),
+                        Bubble::Double(_) => {
+                            bubble_abyss.push(Bubble::Simple(bubble.get_bubbles().len() as i32))
                         }
                     }
                 }
-            }
-            Awatism::Call(_, _) => {
-                let top = bubble_abyss.pop().unwrap();
-                match top {
-                    Bubble::Simple(_) => {
-                        panic!("Expected i32 double bubble");
+                Awatism::Lbl(_arg) => {}
+                Awatism::Jmp(arg) => {
+                    index = code.label(arg)?;
+                }
+                Awatism::Eql => {
+                    let top = bubble_abyss.top().unwrap();
+                    let before_top = bubble_abyss.before_top().unwrap();
+
+                    if !top.is_double()
+                        && !before_top.is_double()
+                        && top.get_val() == before_top.get_val()
+                    {
+                        // execute next line
+                    } else {
+                        index = skip_next(&mut code, index)?;
                     }
-                    Bubble::Double(bubbles) => {
-                        let mut val = vec![];
-                        for bubble in bubbles {
-                            val.push(bubble.get_val() as u8);
-                        }
-                        let val = i32::from_le_bytes(<[u8; 4]>::try_from(val).unwrap());
-                        bubble_abyss.push(Bubble::Simple(index as i32)); // push return position to abyss
-                        index = val as usize; // jump to call position
-                        continue;
+                }
+                Awatism::Lss => {
+                    let top = bubble_abyss.top().unwrap();
+                    let before_top = bubble_abyss.before_top().unwrap();
+
+                    if !top.is_double()
+                        && !before_top.is_double()
+                        && top.get_val() < before_top.get_val()
+                    {
+                        // execute next line
+                    } else {
+                        index = skip_next(&mut code, index)?;
                     }
                 }
-            }
-            Awatism::Ret => {
-                let top = bubble_abyss.pop().unwrap();
-                match top {
-                    Bubble::Simple(val) => {
-                        index = val as usize;
+                Awatism::Gr8 => {
+                    let top = bubble_abyss.top().unwrap();
+                    let before_top = bubble_abyss.before_top().unwrap();
+
+                    if !top.is_double()
+                        && !before_top.is_double()
+                        && top.get_val() > before_top.get_val()
+                    {
+                        // execute next line
+                    } else {
+                        index = skip_next(&mut code, index)?;
                     }
-                    Bubble::Double(_) => {
-                        panic!("Expected return value to be a simple bubble");
+                }
+                Awatism::Syscall => {
+                    if self.syscall(&mut bubble_abyss)? {
+                        break;
                     }
                 }
-            }
-            Awatism::Trm => {
-                break;
-            }
-            // special awatism
-            Awatism::StrLbl(_str_label) => {
-                // only used to calculate position of relative jump from awasm label
-            }
-            Awatism::JmpRelStr(_) => {
-                // only used to calculate position of relative jump from awasm label
-            }
-            Awatism::JmpRel => {
-                let top = bubble_abyss.pop().unwrap();
-
-                // is i32 if is double
-                match top {
-                    Bubble::Simple(val) => {
-                        index = (index as i32 + val) as usize;
+                Awatism::DoublePop => {
+                    // unlike pop a double bubble goes with everything inside it
+                    bubble_abyss.pop();
+                }
+                Awatism::Lib => {
+                    let top = bubble_abyss.pop().unwrap();
+                    match top {
+                        Bubble::Simple(_) => {}
+                        Bubble::Double(bubbles) => {
+                            let fn_name = match bubbles.first() {
+                                Some(bubble) => dynlib::parse_fn_name(bubble)
+                                    .map_err(RuntimeError::InvalidLibName)?,
+                                None => {
+                                    return Err(RuntimeError::InvalidLibName(
+                                        "Expected a function name".to_string(),
+                                    ))
+                                }
+                            };
+
+                            for bubble in self.call_fn(&fn_name, bubbles.get(1))? {
+                                bubble_abyss.push(bubble);
+                                self.check_limits(&bubble_abyss)?;
+                            }
+                        }
+                    }
+                }
+                Awatism::Call(_, _) => {
+                    let top = bubble_abyss.pop().unwrap();
+                    match top {
+                        Bubble::Simple(_) => {
+                            panic!("Expected i32 double bubble");
+                        }
+                        Bubble::Double(bubbles) => {
+                            let mut val = vec![];
+                            for bubble in bubbles {
+                                val.push(bubble.get_val() as u8);
+                            }
+                            let val = i32::from_le_bytes(<[u8; 4]>::try_from(val).unwrap());
+                            bubble_abyss.push(Bubble::Simple(index as i32)); // push return position to abyss
+                            index = val as usize; // jump to call position
+                            continue;
+                        }
                     }
-                    Bubble::Double(bubbles) => {
-                        let mut val = vec![];
-                        for bubble in bubbles {
-                            val.push(bubble.get_val() as u8);
+                }
+                Awatism::Ret => {
+                    let top = bubble_abyss.pop().unwrap();
+                    match top {
+                        Bubble::Simple(val) => {
+                            index = val as usize;
+                        }
+                        Bubble::Double(_) => {
+                            panic!("Expected return value to be a simple bubble");
                         }
-                        let val = i32::from_le_bytes(<[u8; 4]>::try_from(val).unwrap());
-                        index = (index as i32 + val) as usize;
                     }
                 }
+                Awatism::Trm => {
+                    break;
+                }
+                // special awatism
+                Awatism::StrLbl(_str_label) => {
+                    // only used to calculate position of relative jump from awasm label
+                }
+                Awatism::JmpRelStr(_) => {
+                    // only used to calculate position of relative jump from awasm label
+                }
+                Awatism::JmpRel => {
+                    let top = bubble_abyss.pop().unwrap();
+
+                    // is i32 if is double
+                    let val = match top {
+                        Bubble::Simple(val) => val,
+                        Bubble::Double(bubbles) => {
+                            let mut val = vec![];
+                            for bubble in bubbles {
+                                val.push(bubble.get_val() as u8);
+                            }
+                            i32::from_le_bytes(<[u8; 4]>::try_from(val).unwrap())
+                        }
+                    };
+                    index = jump_target(index, val, &mut code)?;
+                    self.check_limits(&bubble_abyss)?;
+                    continue;
+                }
             }
+
+            self.check_limits(&bubble_abyss)?;
+            index += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Index of the instruction after the one `offset` away from a `jro` at `index`, which
+/// may be one past the end to stop the program
+fn jump_target(index: usize, offset: i32, code: &mut Code) -> Result<usize, RuntimeError> {
+    let target = index as i64 + offset as i64 + 1;
+    // a forward jump reads the program up to its target
+    if target >= 0 && (code.load(target as usize)? || target as usize == code.instructions.len()) {
+        Ok(target as usize)
+    } else {
+        Err(RuntimeError::JumpOutOfRange(target))
+    }
+}
+
+/// Index of the last instruction skipped by a false comparison at `index`, a `jro` with
+/// its offset pushed by `blo` x4 and `srn 4` is skipped as a single instruction
+fn skip_next(code: &mut Code, index: usize) -> Result<usize, RuntimeError> {
+    code.load(index + 6)?;
+    Ok(match code.instructions.get(index + 6) {
+        Some(instruction)
+            if matches!(
+                Awatism::from_u8(instruction.op, 0x00),
+                Some(Awatism::JmpRel)
+            ) =>
+        {
+            index + 6
         }
+        _ => index + 1,
+    })
+}
 
-        index += 1;
+fn pop_simple(bubble_abyss: &mut BubbleAbyss) -> Result<i32, String> {
+    match bubble_abyss.pop() {
+        Some(Bubble::Simple(val)) => Ok(val),
+        _ => Err("Expected a simple bubble".to_string()),
     }
 }
 
+pub fn interpet_object(object_vec: Vec<u8>, path: &str) -> Result<(), RuntimeError> {
+    Interpreter::new(path).run(object_vec)
+}
+
 fn print_bubble(
     bubble_abyss: &mut BubbleAbyss,
     bubble: &Bubble,
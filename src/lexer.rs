@@ -0,0 +1,452 @@
+use std::fmt;
+
+use crate::AWA_SCII;
+
+/// Location of a token in awasm source, `line` and `col` start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    /// Span covering both spans, which must be on the same line or in order
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            col: self.col,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// Mnemonics, label references and macro names
+    Ident(String),
+    /// Numeric literal, kept as written and converted where it is used
+    Number(String),
+    /// String literal, `raw` is the text between the quotes before escapes are processed
+    Str {
+        awascii: bool,
+        raw: String,
+    },
+    /// Char literal, `raw` is the text between the quotes before escapes are processed
+    Chr {
+        awascii: bool,
+        raw: String,
+    },
+    /// Label definition `name:`
+    Label(String),
+    /// Directive or macro call `!name`, without the `!`
+    Directive(String),
+    /// Macro argument `$name`, without the `$`
+    MacroArg(String),
+    /// Include path `<path>`, without the angle brackets
+    Path(String),
+    Comma,
+    LParen,
+    RParen,
+    Newline,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = |awascii: &bool| if *awascii { "a" } else { "" };
+        match &self.kind {
+            TokenKind::Ident(name) | TokenKind::Number(name) => write!(f, "{}", name),
+            TokenKind::Str { awascii, raw } => write!(f, "{}\"{}\"", prefix(awascii), raw),
+            TokenKind::Chr { awascii, raw } => write!(f, "{}'{}'", prefix(awascii), raw),
+            TokenKind::Label(name) => write!(f, "{}:", name),
+            TokenKind::Directive(name) => write!(f, "!{}", name),
+            TokenKind::MacroArg(name) => write!(f, "${}", name),
+            TokenKind::Path(path) => write!(f, "<{}>", path),
+            TokenKind::Comma => write!(f, ","),
+            TokenKind::LParen => write!(f, "("),
+            TokenKind::RParen => write!(f, ")"),
+            TokenKind::Newline => writeln!(f),
+        }
+    }
+}
+
+/// Error while tokenizing, parsing or assembling awasm, pointing at the offending source
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// Source file the error occurred in, empty for the top level input
+    pub path: String,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        ParseError {
+            message: message.into(),
+            span,
+            path: String::new(),
+        }
+    }
+
+    /// Attach the source file, unless the error already came from another file
+    pub fn in_file(mut self, path: &str) -> Self {
+        if self.path.is_empty() {
+            self.path = path.to_string();
+        }
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}:", self.path)?;
+        }
+        write!(f, "{}:{}: {}", self.span.line, self.span.col, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !";,()\"'<>$!:".contains(c)
+}
+
+fn is_number(word: &str) -> bool {
+    let digits = word.strip_prefix(['-', '+']).unwrap_or(word);
+    let mut chars = digits.chars();
+    let starts_numeric = match chars.next() {
+        Some(c) if c.is_ascii_digit() => true,
+        Some('.') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+        _ => false,
+    };
+    starts_numeric && (parse_int(word).is_some() || word.parse::<f64>().is_ok())
+}
+
+/// Parse a decimal, hex `0x1F` or binary `0b101` integer literal with an optional sign
+pub fn parse_int(raw: &str) -> Option<i64> {
+    let (negative, digits) = match raw.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, raw.strip_prefix('+').unwrap_or(raw)),
+    };
+    if digits.starts_with(['-', '+']) {
+        return None;
+    }
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i64>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).map(|(_, c)| *c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.pos)
+            .map_or(self.source.len(), |(offset, _)| *offset)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn span_from(&self, start: &Span) -> Span {
+        Span {
+            end: self.offset(),
+            ..*start
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.offset();
+        loop {
+            match self.peek(0) {
+                Some(c) if is_word_char(c) => {
+                    self.bump();
+                }
+                // qualified names like `math::sqrt`
+                Some(':') if self.peek(1) == Some(':') => {
+                    self.bump();
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+        self.source[start..self.offset()].to_string()
+    }
+
+    /// Text up to the closing quote, a backslash always escapes the next character
+    fn quoted(&mut self, quote: char, start: &Span) -> Result<String, ParseError> {
+        self.bump();
+        let content_start = self.offset();
+        loop {
+            match self.peek(0) {
+                Some(c) if c == quote => break,
+                Some('\\') if self.peek(1).is_some_and(|c| c != '\n') => {
+                    self.bump();
+                    self.bump();
+                }
+                Some('\n') | None => {
+                    return Err(ParseError::new(
+                        format!("Missing closing {:?}", quote),
+                        self.span_from(start),
+                    ));
+                }
+                Some(_) => {
+                    self.bump();
+                }
+            }
+        }
+        let raw = self.source[content_start..self.offset()].to_string();
+        self.bump();
+        Ok(raw)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, ParseError> {
+        // skip whitespace and comments
+        loop {
+            match self.peek(0) {
+                Some('\n') => break,
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some(';') => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let start = Span {
+            start: self.offset(),
+            end: self.offset(),
+            line: self.line,
+            col: self.col,
+        };
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let kind = match c {
+            '\n' => {
+                self.bump();
+                TokenKind::Newline
+            }
+            ',' => {
+                self.bump();
+                TokenKind::Comma
+            }
+            '(' => {
+                self.bump();
+                TokenKind::LParen
+            }
+            ')' => {
+                self.bump();
+                TokenKind::RParen
+            }
+            '"' => TokenKind::Str {
+                awascii: false,
+                raw: self.quoted('"', &start)?,
+            },
+            '\'' => TokenKind::Chr {
+                awascii: false,
+                raw: self.quoted('\'', &start)?,
+            },
+            '<' => {
+                self.bump();
+                let path_start = self.offset();
+                while self.peek(0).is_some_and(|c| c != '>' && c != '\n') {
+                    self.bump();
+                }
+                if self.peek(0) != Some('>') {
+                    return Err(ParseError::new(
+                        "Missing closing \">\"",
+                        self.span_from(&start),
+                    ));
+                }
+                let path = self.source[path_start..self.offset()].to_string();
+                self.bump();
+                TokenKind::Path(path)
+            }
+            '!' | '$' => {
+                self.bump();
+                let name = self.word();
+                if name.is_empty() {
+                    return Err(ParseError::new(
+                        format!("Expected a name after \"{}\"", c),
+                        self.span_from(&start),
+                    ));
+                }
+                if c == '!' {
+                    TokenKind::Directive(name)
+                } else {
+                    TokenKind::MacroArg(name)
+                }
+            }
+            c if is_word_char(c) => {
+                let word = self.word();
+                match self.peek(0) {
+                    // AWA-SCII string and char literals
+                    Some(quote @ ('"' | '\'')) if word == "a" => {
+                        let raw = self.quoted(quote, &start)?;
+                        if quote == '"' {
+                            TokenKind::Str { awascii: true, raw }
+                        } else {
+                            TokenKind::Chr { awascii: true, raw }
+                        }
+                    }
+                    Some(':') => {
+                        self.bump();
+                        TokenKind::Label(word)
+                    }
+                    _ if is_number(&word) => TokenKind::Number(word),
+                    _ => TokenKind::Ident(word),
+                }
+            }
+            c => {
+                self.bump();
+                return Err(ParseError::new(
+                    format!("Unexpected character '{}'", c),
+                    self.span_from(&start),
+                ));
+            }
+        };
+
+        Ok(Some(Token {
+            kind,
+            span: self.span_from(&start),
+        }))
+    }
+}
+
+/// Split awasm source into tokens, comments are dropped and line ends are kept
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut lexer = Lexer {
+        source,
+        chars: source.char_indices().collect(),
+        pos: 0,
+        line: 1,
+        col: 1,
+    };
+
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Process escape sequences in the raw text of a string or char literal into the bytes
+/// pushed with `blo`
+///
+/// Supported escapes are `\\`, `\"`, `\'`, `\n`, `\t`, `\0`, `\xNN` and `\u{..}`. With
+/// `awascii` every character is translated to its AWA-SCII index and `\xNN` is an index
+/// into the table, otherwise characters are encoded as UTF-8 and `\u{..}` is available
+pub fn unescape(raw: &str, awascii: bool) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = raw.chars();
+
+    let push_char = |bytes: &mut Vec<u8>, c: char| -> Result<(), String> {
+        if awascii {
+            match AWA_SCII.find(c) {
+                Some(index) => bytes.push(index as u8),
+                None => return Err(format!("{:?} is not representable in AWA-SCII", c)),
+            }
+        } else {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+        }
+        Ok(())
+    };
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push_char(&mut bytes, c)?;
+            continue;
+        }
+
+        match chars.next() {
+            Some(c @ ('\\' | '"' | '\'')) => push_char(&mut bytes, c)?,
+            Some('n') => push_char(&mut bytes, '\n')?,
+            Some('t') => push_char(&mut bytes, '\t')?,
+            Some('0') => push_char(&mut bytes, '\0')?,
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => byte,
+                    _ => {
+                        return Err(format!(
+                            "Invalid escape '\\x{}', expected two hex digits",
+                            hex
+                        ))
+                    }
+                };
+                if awascii && byte as usize >= AWA_SCII.len() {
+                    return Err(format!("'\\x{}' is outside the AWA-SCII table", hex));
+                }
+                bytes.push(byte);
+            }
+            Some('u') if awascii => {
+                return Err("'\\u{..}' is only allowed in ascii strings".to_string())
+            }
+            Some('u') => {
+                let mut code = String::new();
+                if chars.next() != Some('{') {
+                    return Err("Expected '{' after '\\u'".to_string());
+                }
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => code.push(c),
+                        None => return Err("Missing closing '}' in '\\u{..}'".to_string()),
+                    }
+                }
+                let c = u32::from_str_radix(&code, 16)
+                    .ok()
+                    .filter(|_| (1..=6).contains(&code.len()))
+                    .and_then(char::from_u32)
+                    .ok_or(format!("Invalid unicode escape '\\u{{{}}}'", code))?;
+                push_char(&mut bytes, c)?;
+            }
+            Some(c) => return Err(format!("Unknown escape '\\{}'", c)),
+            None => return Err("Trailing '\\' in literal".to_string()),
+        }
+    }
+
+    Ok(bytes)
+}
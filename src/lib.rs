@@ -1,17 +1,29 @@
 pub mod assembler;
+pub mod ast;
+pub mod bindgen;
+pub mod compiler;
 pub mod dynlib;
 pub mod interpreter;
+pub mod lexer;
+pub mod linker;
 pub mod parser;
+pub mod pipeline;
+pub mod sandbox;
+pub mod stdlib;
+pub mod watch;
 
 #[cfg(target_arch = "wasm32")]
 mod awa5_raylib;
 
 pub use assembler::*;
+pub use compiler::{Compiler, Program};
 pub use dynlib::*;
 pub use interpreter::*;
+pub use lexer::ParseError;
 pub use parser::*;
+pub use sandbox::Policy;
+pub use stdlib::StdConfig;
 
-use std::ffi::OsStr;
 use std::fs;
 use std::fs::File;
 use std::io::{self, BufRead, Write};
@@ -40,6 +52,8 @@ pub enum Awatism {
     Eql,
     Lss,
     Gr8,
+    Syscall,
+    DoublePop,
     Lib,
     Call(bool, String), // is_string, string
     Ret,
@@ -74,8 +88,8 @@ impl Awatism {
             0x12 => Some(Awatism::Eql),
             0x13 => Some(Awatism::Lss),
             0x14 => Some(Awatism::Gr8),
-            0x15 => None, // syscall
-            0x16 => None, // double_pop
+            0x15 => Some(Awatism::Syscall),
+            0x16 => Some(Awatism::DoublePop),
             0x17 => Some(Awatism::Lib),
             0x18 => Some(Awatism::JmpRel),
             0x19 => Some(Awatism::Call(false, "".to_string())),
@@ -116,36 +130,9 @@ pub struct Instruction {
 
 pub static AWA_SCII: &str = "AWawJELYHOSIUMjelyhosiumPCNTpcntBDFGRbdfgr0123456789 .,!'()~_/;\n";
 
+/// Name of a file's format from its extension or content, empty when it cannot be read
 pub fn detect_file_format(filename: &str) -> &str {
-    let extension = Path::new(filename)
-        .extension()
-        .and_then(OsStr::to_str)
-        .unwrap();
-    match extension {
-        "awasm" => return "awasm",
-        "awa" => return "awa",
-        "o" => return "o",
-        _ => {}
-    }
-
-    let content = match fs::read_to_string(filename) {
-        Ok(content) => content,
-        Err(_) => return "",
-    };
-
-    let normalized = content
-        .chars()
-        .filter(|&c| c == 'a' || c == 'w' || c == ' ')
-        .collect::<String>()
-        .split_whitespace()
-        .collect::<Vec<&str>>()
-        .join(" ");
-
-    if normalized.starts_with("awa ") {
-        return "awa";
-    }
-
-    "awasm"
+    pipeline::Format::of_file(filename).map_or("", |format| format.name())
 }
 
 pub fn read_binary_file(filename: &str) -> Result<Vec<u8>, io::Error> {
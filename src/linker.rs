@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use crate::{assembler, Awatism, Instruction};
+
+/// Magic bytes starting a relocatable object, flat objects start with an instruction
+pub const OBJECT_MAGIC: &[u8; 4] = b"AWAO";
+const OBJECT_VERSION: u8 = 1;
+
+/// How a relocation site is patched, both sites are `blo` x4, `srn 4` and the jump
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocKind {
+    /// `jro label`, an i32 offset relative to the `jro`
+    Jro,
+    /// `call label`, the absolute instruction index of the label
+    Call,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// Instruction index inside the object
+    pub position: usize,
+    /// Visible to other objects when linking
+    pub global: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    /// Instruction index of the first `blo` of the site
+    pub position: usize,
+    pub kind: RelocKind,
+    pub symbol: String,
+}
+
+/// Assembled code whose label references are left for the linker
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectFile {
+    /// Instructions as `[op, arg]` pairs, relocation sites hold zero offsets
+    pub code: Vec<u8>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl ObjectFile {
+    /// Assemble instructions without resolving labels, `is_global` decides which labels
+    /// other objects may reference
+    pub fn assemble(instructions: &[Instruction], is_global: impl Fn(&str) -> bool) -> Self {
+        let mut object = ObjectFile::default();
+
+        for instruction in instructions {
+            let position = object.code.len() / 2;
+            let (kind, symbol) = match &instruction.awatism {
+                Awatism::StrLbl(label) => {
+                    object.symbols.push(Symbol {
+                        name: label.to_string(),
+                        position,
+                        global: is_global(label),
+                    });
+                    continue;
+                }
+                Awatism::JmpRelStr(label) => (RelocKind::Jro, label),
+                Awatism::Call(true, label) => (RelocKind::Call, label),
+                _ => {
+                    object.code.extend(assembler::assemble_awatism(instruction));
+                    continue;
+                }
+            };
+
+            object.relocations.push(Relocation {
+                position,
+                kind,
+                symbol: symbol.to_string(),
+            });
+            for _ in 0..4 {
+                object.code.extend([0x05, 0x00]); // blo i32 little endian
+            }
+            object.code.extend([0x09, 4]); // srn 4
+            object.code.extend(assembler::assemble_awatism(instruction));
+        }
+
+        object
+    }
+
+    /// Code with the references to its own symbols patched, other sites stay zero
+    pub fn resolve_local(&self) -> Vec<u8> {
+        let mut code = self.code.clone();
+        for relocation in &self.relocations {
+            if let Some(symbol) = self.symbols.iter().find(|s| s.name == relocation.symbol) {
+                patch(&mut code, relocation, 0, symbol.position);
+            }
+        }
+        code
+    }
+
+    pub fn is_object(bytes: &[u8]) -> bool {
+        bytes.starts_with(OBJECT_MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = OBJECT_MAGIC.to_vec();
+        bytes.push(OBJECT_VERSION);
+
+        bytes.extend((self.code.len() as u32).to_le_bytes());
+        bytes.extend(&self.code);
+
+        bytes.extend((self.symbols.len() as u32).to_le_bytes());
+        for symbol in &self.symbols {
+            bytes.push(symbol.global as u8);
+            bytes.extend((symbol.position as u32).to_le_bytes());
+            write_name(&mut bytes, &symbol.name);
+        }
+
+        bytes.extend((self.relocations.len() as u32).to_le_bytes());
+        for relocation in &self.relocations {
+            bytes.push(match relocation.kind {
+                RelocKind::Jro => 0,
+                RelocKind::Call => 1,
+            });
+            bytes.extend((relocation.position as u32).to_le_bytes());
+            write_name(&mut bytes, &relocation.symbol);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if !Self::is_object(bytes) {
+            return Err("Not a relocatable object".to_string());
+        }
+        let mut reader = Reader {
+            bytes,
+            pos: OBJECT_MAGIC.len(),
+        };
+        let version = reader.take(1)?[0];
+        if version != OBJECT_VERSION {
+            return Err(format!("Unsupported object version {}", version));
+        }
+
+        let code_len = reader.u32()? as usize;
+        if !code_len.is_multiple_of(2) {
+            return Err("Object code has an odd length".to_string());
+        }
+        let code = reader.take(code_len)?.to_vec();
+
+        let mut symbols = Vec::new();
+        for _ in 0..reader.u32()? {
+            let global = reader.take(1)?[0] != 0;
+            let position = reader.u32()? as usize;
+            let name = reader.name()?;
+            symbols.push(Symbol {
+                name,
+                position,
+                global,
+            });
+        }
+
+        let mut relocations = Vec::new();
+        for _ in 0..reader.u32()? {
+            let kind = match reader.take(1)?[0] {
+                0 => RelocKind::Jro,
+                1 => RelocKind::Call,
+                kind => return Err(format!("Unknown relocation kind {}", kind)),
+            };
+            let position = reader.u32()? as usize;
+            if (position + 6) * 2 > code.len() {
+                return Err(format!("Relocation at {} is outside the code", position));
+            }
+            let symbol = reader.name()?;
+            relocations.push(Relocation {
+                position,
+                kind,
+                symbol,
+            });
+        }
+
+        Ok(ObjectFile {
+            code,
+            symbols,
+            relocations,
+        })
+    }
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.extend((name.len() as u16).to_le_bytes());
+    bytes.extend(name.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err("Object file is truncated".to_string());
+        }
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let bytes = self.take(2)?;
+        let len = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "Invalid symbol name".to_string())
+    }
+}
+
+/// Merge named objects in order into a flat object that can be run
+///
+/// References resolve to a symbol of the same object first, then to a global symbol of
+/// any object. Every duplicate and undefined symbol is reported, one per line
+pub fn link(objects: &[(String, ObjectFile)]) -> Result<Vec<u8>, String> {
+    let mut errors = Vec::new();
+
+    let mut bases = Vec::new();
+    let mut base = 0;
+    for (_, object) in objects {
+        bases.push(base);
+        base += object.code.len() / 2;
+    }
+
+    let mut globals: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (i, (name, object)) in objects.iter().enumerate() {
+        for symbol in object.symbols.iter().filter(|s| s.global) {
+            let position = bases[i] + symbol.position;
+            if let Some((other, _)) = globals.insert(&symbol.name, (i, position)) {
+                errors.push(format!(
+                    "Duplicate symbol '{}' in {} and {}",
+                    symbol.name, objects[other].0, name
+                ));
+            }
+        }
+    }
+
+    let mut code = Vec::new();
+    for (i, (name, object)) in objects.iter().enumerate() {
+        let mut object_code = object.code.clone();
+        for relocation in &object.relocations {
+            let local = object
+                .symbols
+                .iter()
+                .find(|symbol| symbol.name == relocation.symbol)
+                .map(|symbol| bases[i] + symbol.position);
+            let target = match local.or(globals.get(relocation.symbol.as_str()).map(|g| g.1)) {
+                Some(target) => target,
+                None => {
+                    errors.push(format!(
+                        "Undefined symbol '{}' referenced in {}",
+                        relocation.symbol, name
+                    ));
+                    continue;
+                }
+            };
+
+            patch(&mut object_code, relocation, bases[i], target);
+        }
+        code.extend(object_code);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    Ok(code)
+}
+
+/// Write the offset to `target` into a relocation site of an object placed at `base`
+fn patch(code: &mut [u8], relocation: &Relocation, base: usize, target: usize) {
+    let site = (base + relocation.position) as i32;
+    let value = match relocation.kind {
+        // the interpreter adds one after jumping from the jro, 5 instructions on
+        RelocKind::Jro => target as i32 - site - 6,
+        RelocKind::Call => target as i32,
+    };
+    for (k, byte) in value.to_le_bytes().iter().enumerate() {
+        code[(relocation.position + k) * 2 + 1] = *byte;
+    }
+}
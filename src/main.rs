@@ -2,289 +2,640 @@ extern crate clap;
 
 use awa5_rs::*;
 
-use clap::{Arg, Command};
-use std::collections::HashSet;
-use std::fs;
-use std::path::Path;
+use awa5_rs::pipeline::{self, Format};
+use awa5_rs::watch;
+use clap::{Arg, ArgMatches, Command};
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, process, thread};
 
 fn main() {
     let mut cmd = Command::new("awa5_rs")
         .version("1.0")
         .about("An AWA5.0 CLI tool written in Rust (btw)")
+        .args(input_args())
+        .args(output_args())
+        .args(runtime_args())
         .arg(
-            Arg::new("input")
-                .index(1)
-                .help("File to interpret or convert")
+            Arg::new("bindgen")
+                .long("bindgen")
+                .help("Generate a C shim, header and awasm macros from a binding file, written next to --output or the binding file")
                 .num_args(1),
         )
-        .arg(
-            Arg::new("output")
-                .short('o')
-                .long("output")
-                .help("Output to file with new format .awasm .awa .o")
-                .num_args(1),
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("run")
+                .about("Interpret a program")
+                .args(input_args())
+                .args(runtime_args())
+                .arg(
+                    Arg::new("watch")
+                        .long("watch")
+                        .help("Assemble and run again when the input or a file it includes changes")
+                        .num_args(0),
+                ),
         )
-        .arg(
-            Arg::new("string")
-                .short('s')
-                .long("string")
-                .help("String to interpret or convert")
-                .num_args(1),
+        .subcommand(
+            Command::new("asm")
+                .about("Assemble an awasm file to a relocatable object")
+                .arg(Arg::new("input").required(true).num_args(1))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Relocatable object file")
+                        .required_unless_present("listing")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("listing")
+                        .long("listing")
+                        .help("Write a listing of indices, bytes, awatalk and source lines")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("include")
+                        .short('i')
+                        .long("include")
+                        .help("Include paths separated by ';' for source files")
+                        .num_args(1),
+                ),
         )
-        .arg(
-            Arg::new("awasm")
-                .long("awasm")
-                .help("Parse string as awasm")
-                .num_args(0),
+        .subcommand(
+            Command::new("disasm")
+                .about("Write a program as awasm, to stdout without --output")
+                .args(input_args())
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Awasm file")
+                        .num_args(1),
+                ),
         )
-        .arg(
-            Arg::new("awa")
-                .long("awa")
-                .help("Parse string as awatalk")
-                .num_args(0),
+        .subcommand(
+            Command::new("convert")
+                .about("Convert a program to another format, to stdout without --output")
+                .args(input_args())
+                .args(output_args()),
         )
-        .arg(
-            Arg::new("path")
-                .short('p')
-                .long("path")
-                .help("Search paths separated by ';' for shared libraries")
-                .num_args(1),
+        .subcommand(
+            Command::new("check")
+                .about("Parse and assemble a program, reporting errors without running it")
+                .args(input_args()),
         )
-        .arg(
-            Arg::new("include")
-                .short('i')
-                .long("include")
-                .help("Include paths separated by ';' for source files")
-                .num_args(1),
+        .subcommand(
+            Command::new("link")
+                .about("Link relocatable objects into an object file that can be run")
+                .arg(Arg::new("inputs").required(true).num_args(1..))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Linked object file")
+                        .required(true)
+                        .num_args(1),
+                ),
         );
 
     let matches = cmd.clone().get_matches();
 
-    if matches.contains_id("input") && matches.contains_id("string") {
-        eprintln!(
-            "Warning: Both file input and 'string' flag are given. Please provide only one.\n"
-        );
-        cmd.print_help().unwrap();
+    let result = match matches.subcommand() {
+        Some(("run", sub_matches)) if sub_matches.get_flag("watch") => watch_program(sub_matches),
+        Some(("run", sub_matches)) => run_program(sub_matches),
+        Some(("asm", sub_matches)) => assemble_relocatable(
+            sub_matches.get_one::<String>("input").unwrap(),
+            sub_matches.get_one::<String>("output"),
+            sub_matches.get_one::<String>("listing"),
+            sub_matches
+                .get_one::<String>("include")
+                .map_or("", |include| include.as_str()),
+        ),
+        Some(("disasm", sub_matches)) => convert_program(sub_matches, Some(Format::Awasm)),
+        Some(("convert", sub_matches)) => convert_program(sub_matches, None),
+        Some(("check", sub_matches)) => check_program(sub_matches),
+        Some(("link", sub_matches)) => link_objects(
+            &sub_matches
+                .get_many::<String>("inputs")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            sub_matches.get_one::<String>("output").unwrap(),
+        ),
+        _ => {
+            if let Some(binding_file) = matches.get_one::<String>("bindgen") {
+                generate_bindings(binding_file, matches.get_one::<String>("output"))
+            } else if ["input", "string", "from"]
+                .iter()
+                .all(|id| !matches.contains_id(id))
+                && !matches.get_flag("awasm")
+                && !matches.get_flag("awa")
+            {
+                // nothing to read, stdin needs a format
+                cmd.print_help().unwrap();
+                std::process::exit(1);
+            } else if matches.contains_id("output") || matches.contains_id("to") {
+                // without a command, an output means converting
+                convert_program(&matches, None)
+            } else {
+                run_program(&matches)
+            }
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
         std::process::exit(1);
     }
+}
 
-    let mut path = "/usr/local/lib";
-    let mut include_paths = "";
+/// Arguments choosing the program to read and how to read it
+fn input_args() -> Vec<Arg> {
+    vec![
+        Arg::new("input")
+            .index(1)
+            .help("File to interpret or convert, - for stdin")
+            .num_args(1),
+        Arg::new("string")
+            .short('s')
+            .long("string")
+            .help("String to interpret or convert")
+            .conflicts_with("input")
+            .num_args(1),
+        Arg::new("from")
+            .long("from")
+            .help("Input format awasm, awa or o, instead of detecting it from the extension or content")
+            .conflicts_with_all(["awasm", "awa"])
+            .num_args(1),
+        Arg::new("awasm")
+            .long("awasm")
+            .help("Parse string as awasm")
+            .conflicts_with("awa")
+            .num_args(0),
+        Arg::new("awa")
+            .long("awa")
+            .help("Parse string as awatalk")
+            .num_args(0),
+        Arg::new("lenient")
+            .long("lenient")
+            .help("Decode awatalk up to the first invalid token, skipping unknown opcodes")
+            .num_args(0),
+        Arg::new("include")
+            .short('i')
+            .long("include")
+            .help("Include paths separated by ';' for source files")
+            .num_args(1),
+    ]
+}
 
-    if matches.contains_id("path") {
-        path = matches.get_one::<String>("path").unwrap();
-    }
+/// Arguments choosing where and in which format a converted program is written
+fn output_args() -> Vec<Arg> {
+    vec![
+        Arg::new("output")
+            .short('o')
+            .long("output")
+            .help("Output to file with new format .awasm .awa .o")
+            .num_args(1),
+        Arg::new("to")
+            .long("to")
+            .help("Output format awasm, awa or o, instead of the output file's extension")
+            .num_args(1),
+        Arg::new("wrap")
+            .long("wrap")
+            .help("Wrap awatalk output before the given column")
+            .num_args(1),
+        Arg::new("per-line")
+            .long("per-line")
+            .help("Write each awatalk instruction on its own line")
+            .num_args(0),
+        Arg::new("annotate")
+            .long("annotate")
+            .help("Follow each awatalk instruction with a comment of its awasm")
+            .num_args(0),
+    ]
+}
+
+/// Arguments for the interpreter, its sandbox and the program's argv
+fn runtime_args() -> Vec<Arg> {
+    vec![
+        Arg::new("path")
+            .short('p')
+            .long("path")
+            .help("Search paths separated by ';' for shared libraries")
+            .num_args(1),
+        Arg::new("file-root")
+            .long("file-root")
+            .help("Restrict std file functions to a directory")
+            .num_args(1),
+        Arg::new("no-files")
+            .long("no-files")
+            .help("Disable std file functions")
+            .num_args(0),
+        Arg::new("policy")
+            .long("policy")
+            .help("Sandbox policy file of 'key = value' settings")
+            .num_args(1),
+        Arg::new("no-lib")
+            .long("no-lib")
+            .help("Disable lib calls")
+            .num_args(0),
+        Arg::new("no-env")
+            .long("no-env")
+            .help("Hide environment variables from the env syscall")
+            .num_args(0),
+        Arg::new("allow-lib")
+            .long("allow-lib")
+            .help("Shared libraries allowed for lib calls separated by ';'")
+            .num_args(1),
+        Arg::new("allow-fn")
+            .long("allow-fn")
+            .help("Function names allowed for lib calls separated by ';'")
+            .num_args(1),
+        Arg::new("allow-include")
+            .long("allow-include")
+            .help("Directories allowed for includes separated by ';'")
+            .num_args(1),
+        Arg::new("max-instructions")
+            .long("max-instructions")
+            .help("Maximum number of executed instructions")
+            .num_args(1),
+        Arg::new("max-abyss")
+            .long("max-abyss")
+            .help("Maximum number of bubbles in the abyss")
+            .num_args(1),
+        Arg::new("max-bubble-size")
+            .long("max-bubble-size")
+            .help("Maximum number of bubbles in a double bubble")
+            .num_args(1),
+        Arg::new("limits")
+            .long("limits")
+            .help("Resource limits separated by ',' from instructions=N abyss=N bubble_size=N depth=N bubbles=N timeout=MS")
+            .num_args(1),
+        Arg::new("data")
+            .long("data")
+            .help("File read by red and r3d instead of stdin, for programs read from stdin")
+            .num_args(1),
+        Arg::new("args")
+            .index(2)
+            .help("Arguments passed to the program")
+            .last(true)
+            .num_args(0..),
+    ]
+}
 
-    if matches.contains_id("include") {
-        include_paths = matches.get_one::<String>("include").unwrap();
+/// Value of an argument the subcommand may not define
+fn arg_value<'a>(matches: &'a ArgMatches, id: &str) -> Option<&'a String> {
+    matches.try_get_one::<String>(id).ok().flatten()
+}
+
+/// Flag the subcommand may not define
+fn arg_flag(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.try_get_one::<bool>(id), Ok(Some(true)))
+}
+
+/// Input format from --from, --awasm or --awa, then the input file, `None` when it is
+/// detected from a string or stdin
+fn input_format(matches: &ArgMatches) -> Result<Option<Format>, String> {
+    if let Some(from) = arg_value(matches, "from") {
+        return Format::from_name(from)
+            .map(Some)
+            .map_err(|err| format!("--from: {}", err));
+    }
+    if arg_flag(matches, "awasm") {
+        return Ok(Some(Format::Awasm));
     }
+    if arg_flag(matches, "awa") {
+        return Ok(Some(Format::Awa));
+    }
+    match arg_value(matches, "input").filter(|input_file| *input_file != "-") {
+        Some(input_file) => Format::of_file(input_file)
+            .map(Some)
+            .ok_or(format!("Could not read {}", input_file)),
+        None => Ok(None),
+    }
+}
 
-    if matches.contains_id("input") {
-        let input_file = matches.get_one::<String>("input").unwrap();
-
-        let file_type = detect_file_format(input_file);
-
-        match file_type {
-            "awasm" => {
-                let lines = read_lines(input_file).unwrap();
-
-                let mut macro_table = parser::awasm::MacroTable::new();
-                let mut already_included: HashSet<String> = HashSet::new();
-                let mut label_included: HashSet<String> = HashSet::new();
-                let instructions = parser::awasm::parse_lines(
-                    &mut macro_table,
-                    &mut already_included,
-                    &mut label_included,
-                    include_paths,
-                    "",
-                    lines,
-                );
-
-                let object_vec = assembler::make_object_vec(&instructions);
-
-                if matches.contains_id("output") {
-                    let output_file = matches.get_one::<String>("output").unwrap();
-
-                    match Path::new(output_file)
-                        .extension()
-                        .and_then(|ext| ext.to_str())
-                        .unwrap()
-                    {
-                        "awasm" => {
-                            let _ = fs::copy(input_file, output_file);
-                        }
-                        "awa" => {
-                            let result = assembler::object_to_awa(&object_vec);
-                            let _ = write_string_file(output_file, &result);
-                        }
-                        "o" => {
-                            let _ = write_object_file(output_file, object_vec);
-                        }
-                        _ => {}
-                    }
-                } else {
-                    interpet_object(object_vec, path);
-                }
-            }
-            "awa" => {
-                let content = fs::read_to_string(input_file).unwrap();
-
-                let instructions = parser::awatalk::parse_string(&content);
-
-                let object_vec = assembler::make_object_vec(&instructions);
-
-                if matches.contains_id("output") {
-                    let output_file = matches.get_one::<String>("output").unwrap();
-
-                    match Path::new(output_file)
-                        .extension()
-                        .and_then(|ext| ext.to_str())
-                        .unwrap()
-                    {
-                        "awasm" => {
-                            let result = assembler::object_to_awasm(&object_vec);
-                            let _ = write_string_file(output_file, &result);
-                        }
-                        "awa" => {
-                            let _ = fs::copy(input_file, output_file);
-                        }
-                        "o" => {
-                            let _ = write_object_file(output_file, object_vec);
-                        }
-                        _ => {}
-                    }
-                } else {
-                    interpet_object(object_vec, path);
-                }
-            }
-            "o" => match read_binary_file(input_file) {
-                Ok(binary_data) => {
-                    if matches.contains_id("output") {
-                        let output_file = matches.get_one::<String>("output").unwrap();
-
-                        match Path::new(output_file)
-                            .extension()
-                            .and_then(|ext| ext.to_str())
-                            .unwrap()
-                        {
-                            "awasm" => {
-                                let result = assembler::object_to_awasm(&binary_data);
-                                let _ = write_string_file(output_file, &result);
-                            }
-                            "awa" => {
-                                let result = assembler::object_to_awa(&binary_data);
-                                let _ = write_string_file(output_file, &result);
-                            }
-                            "o" => {
-                                let _ = fs::copy(input_file, output_file);
-                            }
-                            _ => {}
-                        }
-                    } else {
-                        interpet_object(binary_data, path);
-                    }
-                }
-                Err(err) => {
-                    eprintln!("Error reading file: {}", err);
-                }
-            },
-            _ => panic!("Could not autodetect file type"),
+/// Reader of the input file, the string, or stdin, with its format and the name errors
+/// are reported under
+fn open_input(matches: &ArgMatches) -> Result<(Box<dyn Read>, Format, String), String> {
+    let format = input_format(matches)?;
+    let input_file = arg_value(matches, "input").filter(|input_file| *input_file != "-");
+    let (reader, name): (Box<dyn Read>, String) = match (input_file, arg_value(matches, "string")) {
+        (Some(input_file), _) => {
+            let file = File::open(input_file)
+                .map_err(|err| format!("Could not read {}: {}", input_file, err))?;
+            (Box::new(file), input_file.to_string())
         }
-    } else {
-        let mut input_string = String::new();
-        if matches.contains_id("string") {
-            input_string = matches.get_one::<String>("string").unwrap().clone();
-        } else {
-            let _ = std::io::stdin().read_line(&mut input_string);
+        (None, Some(input_string)) => (
+            Box::new(io::Cursor::new(input_string.clone().into_bytes())),
+            String::new(),
+        ),
+        // `-` or no input is stdin, not locked so red and r3d can read the rest of it
+        (None, None) => (Box::new(io::stdin()), String::new()),
+    };
+
+    match format {
+        Some(format) => Ok((reader, format, name)),
+        None => {
+            let (detection, reader) = pipeline::detect_reader(reader)?;
+            Ok((Box::new(reader), detection.format, name))
         }
+    }
+}
 
-        if matches.get_flag("awasm") && matches.get_flag("awa") {
-            eprintln!(
-                "Warning: Both 'awasm' and 'awa' flags are given. Please provide only one.\n"
-            );
-            cmd.print_help().unwrap();
-            std::process::exit(1);
-        }
+fn pipeline_options(matches: &ArgMatches, policy: &Policy) -> Result<pipeline::Options, String> {
+    let include_paths = arg_value(matches, "include").map_or("", |include| include.as_str());
+    let wrap = match arg_value(matches, "wrap") {
+        Some(wrap) => Some(
+            wrap.parse()
+                .map_err(|_| format!("--wrap: Expected a column, found '{}'", wrap))?,
+        ),
+        None => None,
+    };
+
+    Ok(pipeline::Options {
+        include_paths: policy.filter_include_paths(include_paths)?,
+        include_dirs: policy.include_dirs.clone(),
+        lenient: arg_flag(matches, "lenient"),
+        defines: Vec::new(),
+        awa_format: AwaFormat {
+            wrap,
+            per_line: arg_flag(matches, "per-line"),
+            annotate: arg_flag(matches, "annotate"),
+        },
+    })
+}
+
+fn read_input(matches: &ArgMatches, policy: &Policy) -> Result<Vec<u8>, String> {
+    let options = pipeline_options(matches, policy)?;
+    let (reader, format, name) = open_input(matches)?;
+    pipeline::read_program(reader, format, &name, &options)
+}
 
-        if !matches.get_flag("awasm") && !matches.get_flag("awa") {
-            eprintln!("Warning: Neither 'awasm' and 'awa' flags are given. Please provide at least one.\n");
-            cmd.print_help().unwrap();
-            std::process::exit(1);
+fn run_program(matches: &ArgMatches) -> Result<(), String> {
+    let policy = build_policy(matches)?;
+    // red and r3d read from --data when stdin holds the program
+    let data = match arg_value(matches, "data") {
+        Some(data_file) => {
+            let file = File::open(data_file)
+                .map_err(|err| format!("Could not read {}: {}", data_file, err))?;
+            Some(Box::new(io::BufReader::new(file)) as Box<dyn BufRead>)
         }
+        None => None,
+    };
+    let options = pipeline_options(matches, &policy)?;
+    let (reader, format, name) = open_input(matches)?;
+
+    let path = arg_value(matches, "path").map_or("/usr/local/lib", |path| path.as_str());
+    let std_config = StdConfig {
+        allow_files: !arg_flag(matches, "no-files"),
+        file_root: arg_value(matches, "file-root").map(PathBuf::from),
+    };
+    let program_args: Vec<String> = matches
+        .get_many::<String>("args")
+        .map_or(Vec::new(), |args| args.cloned().collect());
+
+    let program = |interpreter: &mut Interpreter| {
+        pipeline::run_program(reader, format, &name, &options, interpreter)
+    };
+    run_object(program, path, &std_config, &policy, &program_args, data)
+}
 
-        if matches.get_flag("awasm") {
-            let lines = input_string.lines().map(|line| line.to_string());
-
-            let mut macro_table = parser::awasm::MacroTable::new();
-            let mut already_included: HashSet<String> = HashSet::new();
-            let mut label_included: HashSet<String> = HashSet::new();
-            let instructions = parser::awasm::parse_lines(
-                &mut macro_table,
-                &mut already_included,
-                &mut label_included,
-                include_paths,
-                "",
-                lines,
-            );
-
-            let object_vec = assembler::make_object_vec(&instructions);
-
-            if matches.contains_id("output") {
-                let output_file = matches.get_one::<String>("output").unwrap();
-
-                match Path::new(output_file)
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .unwrap()
-                {
-                    "awasm" => {
-                        let result = assembler::object_to_awasm(&object_vec);
-                        let _ = write_string_file(output_file, &result);
-                    }
-                    "awa" => {
-                        let result = assembler::object_to_awa(&object_vec);
-                        let _ = write_string_file(output_file, &result);
-                    }
-                    "o" => {
-                        let _ = write_object_file(output_file, object_vec);
-                    }
-                    _ => {}
+/// Run the input in a child process, restarting it when the input or a file it includes
+/// changes. Files are polled so no platform notifications are needed, and diagnostics are
+/// reported while watching continues
+fn watch_program(matches: &ArgMatches) -> Result<(), String> {
+    let input_file = arg_value(matches, "input")
+        .filter(|input_file| *input_file != "-")
+        .ok_or("--watch needs an input file")?;
+    let policy = build_policy(matches)?;
+    let options = pipeline_options(matches, &policy)?;
+    let format = input_format(matches)?.unwrap_or(Format::Awasm);
+
+    let child_args = watch::child_args(env::args().skip(1));
+    let exe = env::current_exe().map_err(|err| format!("Could not restart: {}", err))?;
+
+    loop {
+        let (result, mut files) = match format {
+            Format::Awasm => pipeline::assemble_awasm_file(input_file, &options),
+            _ => (
+                File::open(input_file)
+                    .map_err(|err| format!("Could not read {}: {}", input_file, err))
+                    .and_then(|file| pipeline::read_program(file, format, input_file, &options)),
+                Vec::new(),
+            ),
+        };
+        files.push(input_file.to_string());
+
+        let mut child = match result {
+            Ok(_) => Some(
+                process::Command::new(&exe)
+                    .args(&child_args)
+                    .spawn()
+                    .map_err(|err| format!("Could not run {}: {}", input_file, err))?,
+            ),
+            Err(err) => {
+                eprintln!("{}", err);
+                None
+            }
+        };
+
+        let snapshot = watch::Snapshot::new(files);
+        let changed = loop {
+            thread::sleep(Duration::from_millis(500));
+            if let Some(status) = child
+                .as_mut()
+                .and_then(|child| child.try_wait().ok().flatten())
+            {
+                if !status.success() {
+                    eprintln!("{} stopped with {}", input_file, status);
                 }
-            } else {
-                interpet_object(object_vec, path);
+                child = None;
             }
+            if let Some(changed) = snapshot.changed() {
+                break changed;
+            }
+        };
+
+        if let Some(mut child) = child {
+            let _ = child.kill();
+            let _ = child.wait();
         }
+        eprintln!("{} changed, restarting", changed);
+    }
+}
 
-        if matches.get_flag("awa") {
-            let instructions = parser::awatalk::parse_string(&input_string);
-
-            let object_vec = assembler::make_object_vec(&instructions);
-
-            if matches.contains_id("output") {
-                let output_file = matches.get_one::<String>("output").unwrap();
-
-                match Path::new(output_file)
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .unwrap()
-                {
-                    "awasm" => {
-                        let result = assembler::object_to_awasm(&object_vec);
-                        let _ = write_string_file(output_file, &result);
-                    }
-                    "awa" => {
-                        let result = assembler::object_to_awa(&object_vec);
-                        let _ = write_string_file(output_file, &result);
-                    }
-                    "o" => {
-                        let _ = write_object_file(output_file, object_vec);
-                    }
-                    _ => {}
-                }
-            } else {
-                interpet_object(object_vec, path);
-            }
+/// Convert the input to `to`, or the format of --to or the output file's extension
+fn convert_program(matches: &ArgMatches, to: Option<Format>) -> Result<(), String> {
+    let output_file = arg_value(matches, "output");
+    let to = match (to, arg_value(matches, "to"), output_file) {
+        (Some(to), _, _) => to,
+        (None, Some(to), _) => Format::from_name(to).map_err(|err| format!("--to: {}", err))?,
+        (None, None, Some(output_file)) => Format::from_path(output_file).ok_or(format!(
+            "Could not tell the format of {} from its extension, use --to awasm|awa|o",
+            output_file
+        ))?,
+        (None, None, None) => {
+            return Err("Give the output format with --to or --output".to_string())
         }
+    };
+
+    let policy = build_policy(matches)?;
+    let options = pipeline_options(matches, &policy)?;
+    let (reader, from, name) = open_input(matches)?;
+    let bytes = pipeline::convert(reader, from, to, &name, &options)?;
+
+    match output_file {
+        Some(output_file) => fs::write(output_file, bytes)
+            .map_err(|err| format!("Could not write {}: {}", output_file, err)),
+        None => io::stdout()
+            .write_all(&bytes)
+            .map_err(|err| format!("Could not write output: {}", err)),
     }
 }
 
+fn check_program(matches: &ArgMatches) -> Result<(), String> {
+    read_input(matches, &build_policy(matches)?).map(|_| ())
+}
+
+fn generate_bindings(binding_file: &str, output: Option<&String>) -> Result<(), String> {
+    let content = fs::read_to_string(binding_file)
+        .map_err(|err| format!("Could not read {}: {}", binding_file, err))?;
+    let bindings =
+        bindgen::parse_bindings(&content).map_err(|err| format!("{}:{}", binding_file, err))?;
+
+    // output paths share the stem of --output, or of the binding file
+    let base = Path::new(output.map_or(binding_file, |output| output.as_str())).with_extension("");
+    let stem = base
+        .file_name()
+        .and_then(|stem| stem.to_str())
+        .ok_or(format!("Invalid output path {}", base.display()))?;
+    let header_name = format!("{}.h", stem);
+
+    let files = [
+        (
+            base.with_file_name(&header_name),
+            bindgen::generate_header(&bindings, stem),
+        ),
+        (
+            base.with_file_name(format!("{}_shim.c", stem)),
+            bindgen::generate_c(&bindings, &header_name),
+        ),
+        (
+            base.with_file_name(format!("{}.awasm", stem)),
+            bindgen::generate_awasm(&bindings),
+        ),
+    ];
+    for (path, content) in files {
+        let filename = path
+            .to_str()
+            .ok_or(format!("Invalid output path {}", path.display()))?;
+        write_string_file(filename, &content)
+            .map_err(|err| format!("Could not write {}: {}", filename, err))?;
+    }
+
+    Ok(())
+}
+
+fn assemble_relocatable(
+    input_file: &str,
+    output_file: Option<&String>,
+    listing_file: Option<&String>,
+    include_paths: &str,
+) -> Result<(), String> {
+    let options = pipeline::Options {
+        include_paths: include_paths.to_string(),
+        ..Default::default()
+    };
+    let (object, listing) =
+        pipeline::assemble_relocatable(input_file, &options, listing_file.is_some())?;
+
+    if let (Some(listing_file), Some(listing)) = (listing_file, listing) {
+        write_string_file(listing_file, &listing)
+            .map_err(|err| format!("Could not write {}: {}", listing_file, err))?;
+    }
+
+    if let Some(output_file) = output_file {
+        write_object_file(output_file, object.to_bytes())
+            .map_err(|err| format!("Could not write {}: {}", output_file, err))?;
+    }
+    Ok(())
+}
+
+fn link_objects(input_files: &[&String], output_file: &str) -> Result<(), String> {
+    let mut objects = Vec::new();
+    for input_file in input_files {
+        let bytes = read_binary_file(input_file)
+            .map_err(|err| format!("Could not read {}: {}", input_file, err))?;
+        let object = linker::ObjectFile::from_bytes(&bytes)
+            .map_err(|err| format!("{}: {}", input_file, err))?;
+        objects.push((input_file.to_string(), object));
+    }
+
+    let object_vec = linker::link(&objects)?;
+    write_object_file(output_file, object_vec)
+        .map_err(|err| format!("Could not write {}: {}", output_file, err))
+}
+
+fn build_policy(matches: &ArgMatches) -> Result<Policy, String> {
+    let mut policy = match arg_value(matches, "policy") {
+        Some(filename) => Policy::from_file(filename)?,
+        None => Policy::default(),
+    };
+
+    if arg_flag(matches, "no-lib") {
+        policy.disable_lib = true;
+    }
+    if arg_flag(matches, "no-env") {
+        policy.disable_env = true;
+    }
+
+    // command line flags are applied on top of the policy file
+    let settings = [
+        ("allow-lib", "allow_lib"),
+        ("allow-fn", "allow_fn"),
+        ("allow-include", "include_dir"),
+        ("max-instructions", "max_instructions"),
+        ("max-abyss", "max_abyss"),
+        ("max-bubble-size", "max_bubble_size"),
+    ];
+    for (flag, key) in settings {
+        if let Some(value) = arg_value(matches, flag) {
+            policy
+                .apply(&format!("{} = {}", key, value))
+                .map_err(|err| format!("--{}: {}", flag, err))?;
+        }
+    }
+
+    if let Some(limits) = arg_value(matches, "limits") {
+        policy
+            .limits
+            .apply(limits)
+            .map_err(|err| format!("--limits: {}", err))?;
+    }
+
+    Ok(policy)
+}
+
+/// Run a program with `args` as its argv and `data` read by red and r3d, exiting with the
+/// code the program set
+fn run_object(
+    program: impl FnOnce(&mut Interpreter) -> Result<(), String>,
+    path: &str,
+    std_config: &StdConfig,
+    policy: &Policy,
+    args: &[String],
+    data: Option<Box<dyn BufRead>>,
+) -> Result<(), String> {
+    let mut interpreter = Interpreter::with_policy(path, policy.clone());
+    interpreter.set_std_config(std_config);
+    interpreter.set_args(args.to_vec());
+    if let Some(data) = data {
+        interpreter.set_input(data);
+    }
+    program(&mut interpreter)?;
+    if let Some(code) = interpreter.exit_code() {
+        std::process::exit(code);
+    }
+    Ok(())
+}
@@ -1,13 +1,16 @@
 pub mod awasm {
-    use core::panic;
     use std::{
         collections::{HashMap, HashSet},
         fs::File,
         io::{self, BufRead},
-        path::Path,
+        path::{Component, Path, PathBuf},
     };
 
-    use crate::{Awatism, Instruction, AWA_SCII};
+    use crate::{
+        ast::{self, Statement},
+        lexer::{self, ParseError, Span, Token, TokenKind},
+        Awatism, Instruction,
+    };
 
     enum MacroResult {
         VecOnly(Vec<Awatism>),
@@ -30,18 +33,68 @@ pub mod awasm {
         }
     }
 
-    type MacroFn = fn(&str) -> MacroResult;
+    /// Builtin macro receiving the tokens following its name
+    type MacroFn = fn(&[Token]) -> Result<MacroResult, String>;
+
+    /// Maximum nesting of macro expansions, stops macros that call themselves forever
+    const MAX_MACRO_DEPTH: usize = 64;
+
+    /// Every awasm instruction mnemonic
+    pub const MNEMONICS: &[&str] = &[
+        "nop", "prn", "pr1", "red", "r3d", "blo", "sbm", "pop", "dpl", "srn", "mrg", "4dd", "sub",
+        "mul", "div", "cnt", "lbl", "jmp", "jro", "eql", "lss", "gr8", "sys", "p0p", "lib", "call",
+        "ret", "trm",
+    ];
 
     #[derive(Debug)]
     struct UserMacro {
         args: Vec<String>,
-        lines: Vec<String>,
+        /// The last argument collects the remaining call arguments
+        variadic: bool,
+        lines: Vec<Vec<Token>>,
     }
 
     #[derive(Debug)]
     pub struct MacroTable {
         builtins: HashMap<String, MacroFn>,
         user_def: HashMap<String, UserMacro>,
+        /// Constants assigned with `!set`
+        constants: HashMap<String, i64>,
+        /// Number of user macro expansions, used to give macro-local labels a unique suffix
+        expansions: usize,
+        /// Path of the file behind each module name
+        modules: HashMap<String, String>,
+        /// Qualified names of exported labels and macros, with where they were exported
+        exports: HashMap<String, (String, Span)>,
+        /// Label references resolved once every module has been parsed
+        label_refs: Vec<LabelRef>,
+        /// Canonical paths of the files currently being parsed, innermost last
+        include_stack: Vec<String>,
+        /// Keep undefined label references for the linker instead of failing
+        relocatable: bool,
+        /// Canonical directories every included file must be inside, when restricted
+        include_dirs: Option<Vec<PathBuf>>,
+        /// Origin of every parsed awatism in order, when recording
+        origins: Option<Vec<Origin>>,
+    }
+
+    /// Source line an awatism was parsed from
+    #[derive(Debug, Clone)]
+    pub struct Origin {
+        pub path: String,
+        pub line: usize,
+        pub text: String,
+        /// User macros being expanded, outermost first, then the builtin macro
+        pub macros: Vec<String>,
+    }
+
+    /// Reference to a label from `jro` or `call`, resolved after all files are parsed
+    #[derive(Debug)]
+    struct LabelRef {
+        name: String,
+        module: String,
+        path: String,
+        span: Span,
     }
 
     impl MacroTable {
@@ -49,77 +102,236 @@ pub mod awasm {
             let mut table = MacroTable {
                 builtins: HashMap::new(),
                 user_def: HashMap::new(),
+                constants: HashMap::new(),
+                expansions: 0,
+                modules: HashMap::new(),
+                exports: HashMap::new(),
+                label_refs: vec![],
+                include_stack: vec![],
+                relocatable: false,
+                include_dirs: None,
+                origins: None,
             };
 
-            table.builtins.insert("!i32".to_string(), process_i32);
-            table.builtins.insert("!f32".to_string(), process_f32);
-            table.builtins.insert("!chr".to_string(), process_chr);
-            table.builtins.insert("!str".to_string(), process_str);
-            table.builtins.insert("!_i32".to_string(), |token| {
-                let mut res = vec![Awatism::Blo(0x0)];
-                res.extend(process_i32(token).get_vec());
-                res.push(Awatism::Srn(2));
-                MacroResult::VecOnly(res)
+            // !i32 1234 -> blo 210, blo 4, blo 0, blo 0, srn 4
+            table
+                .builtins
+                .insert("i32".to_string(), |tokens| process_i32(single(tokens)?));
+            // !f32 1.5 -> blo 0, blo 0, blo 192, blo 63, srn 4
+            table
+                .builtins
+                .insert("f32".to_string(), |tokens| process_f32(single(tokens)?));
+            // !i64 1234 -> blo 210, blo 4, blo 0, blo 0, blo 0, blo 0, blo 0, blo 0, srn 8
+            table.builtins.insert("i64".to_string(), |tokens| {
+                let token = single(tokens)?;
+                let value = match &token.kind {
+                    TokenKind::Number(number) => lexer::parse_int(number)
+                        .ok_or_else(|| format!("Invalid i64 '{}'", number))?,
+                    _ => return Err(format!("Expected an i64, found '{}'", token)),
+                };
+                Ok(MacroResult::VecOnly(push_bytes(&value.to_le_bytes())))
+            });
+            // !f64 1.5 -> blo 0, blo 0, blo 0, blo 0, blo 0, blo 0, blo 248, blo 63, srn 8
+            table.builtins.insert("f64".to_string(), |tokens| {
+                let token = single(tokens)?;
+                let value: f64 = match &token.kind {
+                    TokenKind::Number(number) => number
+                        .parse()
+                        .map_err(|_| format!("Invalid f64 '{}'", number))?,
+                    _ => return Err(format!("Expected an f64, found '{}'", token)),
+                };
+                Ok(MacroResult::VecOnly(push_bytes(&value.to_le_bytes())))
+            });
+            // !bytes 0xCA 0xFE -> blo 202, blo 254, srn 2
+            table.builtins.insert("bytes".to_string(), |tokens| {
+                let mut bytes = Vec::new();
+                for token in tokens.iter().filter(|t| t.kind != TokenKind::Comma) {
+                    let value = match &token.kind {
+                        TokenKind::Number(number) => lexer::parse_int(number),
+                        _ => None,
+                    };
+                    match value {
+                        Some(value @ -128..=255) => bytes.push(value as u8),
+                        _ => return Err(format!("Expected a byte, found '{}'", token)),
+                    }
+                }
+                if bytes.is_empty() || bytes.len() > 31 {
+                    return Err("!bytes takes 1 to 31 bytes".to_string());
+                }
+                Ok(MacroResult::VecOnly(push_bytes(&bytes)))
+            });
+            // !chr 'a' -> blo 97
+            table
+                .builtins
+                .insert("chr".to_string(), |tokens| process_chr(single(tokens)?));
+            // !str "ab" -> blo 98, blo 97, srn 2
+            table
+                .builtins
+                .insert("str".to_string(), |tokens| process_str(single(tokens)?));
+            // !_i32 5 -> blo 0, !i32 5, srn 2
+            table.builtins.insert("_i32".to_string(), |tokens| {
+                Ok(tag_arg((0x0, 0x0), process_i32(single(tokens)?)?))
+            });
+            // !_f32 1.5 -> blo 0, !f32 1.5, srn 2
+            table.builtins.insert("_f32".to_string(), |tokens| {
+                Ok(tag_arg((0x0, 0x0), process_f32(single(tokens)?)?))
+            });
+            // !_chr 'a' -> blo 2, !chr 'a', srn 2 and blo 1 for AWA-SCII chars
+            table.builtins.insert("_chr".to_string(), |tokens| {
+                Ok(tag_arg((0x2, 0x1), process_chr(single(tokens)?)?))
             });
-            table.builtins.insert("!_f32".to_string(), |token| {
-                let mut res = vec![Awatism::Blo(0x0)];
-                res.extend(process_f32(token).get_vec());
-                res.push(Awatism::Srn(2));
-                MacroResult::VecOnly(res)
+            // !_str "ab" -> blo 4, !str "ab", srn 2 and blo 3 for AWA-SCII strings
+            table.builtins.insert("_str".to_string(), |tokens| {
+                Ok(tag_arg((0x4, 0x3), process_str(single(tokens)?)?))
             });
-            table.builtins.insert("!_chr".to_string(), |token| {
-                let (process, awascii) = process_chr(token).get_vec_and_bool();
-                let mut res = vec![Awatism::Blo(if awascii { 0x1 } else { 0x2 })];
-                res.extend(process);
-                res.push(Awatism::Srn(2));
-                MacroResult::VecAndBool(res, awascii)
+            // !libcall "fn", 1, 2.5 -> !str "fn", !_i32 1, !_f32 2.5, srn 2, srn 2, lib
+            // and !libcall "fn" -> !str "fn", srn 1, lib
+            table.builtins.insert("libcall".to_string(), |tokens| {
+                let args = ast::split_args(tokens);
+                let name = match args.first() {
+                    Some([token]) => token,
+                    _ => return Err("Expected !libcall \"name\", args...".to_string()),
+                };
+                if args.len() > 32 {
+                    return Err("!libcall takes at most 31 arguments".to_string());
+                }
+                let mut res = process_str(name)?.get_vec();
+                for arg in &args[1..] {
+                    match arg {
+                        [token] => res.extend(lib_arg(token)?.get_vec()),
+                        _ => return Err("Expected a literal argument".to_string()),
+                    }
+                }
+                if args.len() > 1 {
+                    res.push(Awatism::Srn(args.len() as u8 - 1));
+                    res.push(Awatism::Srn(2));
+                } else {
+                    res.push(Awatism::Srn(1));
+                }
+                res.push(Awatism::Lib);
+                Ok(MacroResult::VecOnly(res))
+            });
+            // !ifeq label -> eql, jro label, pop, pop
+            table.builtins.insert("ifeq".to_string(), |tokens| {
+                branch(Awatism::Eql, single(tokens)?)
             });
-            table.builtins.insert("!_str".to_string(), |token| {
-                let (process, awascii) = process_str(token).get_vec_and_bool();
-                let mut res = vec![Awatism::Blo(if awascii { 0x3 } else { 0x4 })];
-                res.extend(process);
-                res.push(Awatism::Srn(2));
-                MacroResult::VecAndBool(res, awascii)
+            // !iflss label -> lss, jro label, pop, pop
+            table.builtins.insert("iflss".to_string(), |tokens| {
+                branch(Awatism::Lss, single(tokens)?)
+            });
+            // !ifgr8 label -> gr8, jro label, pop, pop
+            table.builtins.insert("ifgr8".to_string(), |tokens| {
+                branch(Awatism::Gr8, single(tokens)?)
             });
 
             table
         }
 
-        fn is_builtin(&self, key: &str) -> bool {
-            self.builtins.contains_key(key)
-        }
-
         fn get_builtin(&self, key: &str) -> Option<MacroFn> {
             self.builtins.get(key).copied()
         }
 
-        fn add_user_def(&mut self, key: &str, args: Vec<String>, lines: Vec<String>) {
-            self.user_def
-                .insert(key.to_string(), UserMacro { args, lines });
+        fn add_user_def(
+            &mut self,
+            key: &str,
+            args: Vec<String>,
+            variadic: bool,
+            lines: Vec<Vec<Token>>,
+        ) {
+            self.user_def.insert(
+                key.to_string(),
+                UserMacro {
+                    args,
+                    variadic,
+                    lines,
+                },
+            );
         }
 
         fn get_user_def(&self, key: &str) -> Option<&UserMacro> {
             self.user_def.get(key)
         }
-    }
 
-    fn parse_function_call(s: &str) -> (&str, Vec<String>) {
-        if let Some(pos) = s.find('(') {
-            let function_name = &s[..pos];
+        /// Only include files inside `dirs`, such as the `include_dirs` of a sandbox policy
+        pub fn restrict_includes(&mut self, dirs: &[PathBuf]) {
+            self.include_dirs = Some(
+                dirs.iter()
+                    .filter_map(|dir| dir.canonicalize().ok())
+                    .collect(),
+            );
+        }
 
-            let args_str = &s[pos + 1..s.len() - 1];
+        /// Leave `jro` and `call` targets that are not defined for the linker to resolve
+        pub fn set_relocatable(&mut self, relocatable: bool) {
+            self.relocatable = relocatable;
+        }
 
-            let args: Vec<String> = args_str
-                .split(',')
-                .map(|arg| arg.trim().to_string())
-                .collect();
+        /// Assign a constant before parsing, as if by `!set name, value`
+        pub fn define(&mut self, name: &str, value: i64) {
+            self.constants.insert(name.to_string(), value);
+        }
 
-            (function_name, args)
-        } else {
-            (s, Vec::new())
+        /// Record the origin of each parsed awatism, for listings
+        pub fn record_origins(&mut self) {
+            self.origins = Some(vec![]);
+        }
+
+        /// Origins of the parsed instructions, in the same order
+        pub fn origins(&self) -> &[Origin] {
+            self.origins.as_deref().unwrap_or(&[])
+        }
+
+        /// Whether a parsed label can be referenced from other objects, labels of the main
+        /// file and exported labels are, local and macro-local labels are not
+        pub fn is_global_label(&self, label: &str) -> bool {
+            self.exports.contains_key(label)
+                || !(label.contains("::") || label.contains('.') || label.contains('@'))
         }
     }
 
+    /// Macro being recorded between `!def` and `!end`
+    struct MacroDef {
+        name: String,
+        args: Vec<String>,
+        variadic: bool,
+        lines: Vec<Vec<Token>>,
+        span: Span,
+        /// Number of nested `!def`s being recorded as part of the body
+        depth: usize,
+    }
+
+    /// An open `!if` block
+    struct Condition {
+        value: bool,
+        /// Whether the enclosing block is assembled
+        parent_active: bool,
+        in_else: bool,
+        span: Span,
+    }
+
+    struct ParseState<'a> {
+        macro_table: &'a mut MacroTable,
+        already_included: &'a mut HashSet<String>,
+        label_included: &'a mut HashSet<String>,
+        include_paths: &'a str,
+        current_path: &'a str,
+        /// Canonical path of the current file, used by `!once`
+        file_key: String,
+        /// Module name prefixed to labels and macros, empty for the main file
+        module: &'a str,
+        defining: Option<MacroDef>,
+        conditions: Vec<Condition>,
+        /// User macros being expanded with the span of their call
+        expanding: Vec<(String, Span)>,
+        /// Lines of the current file
+        lines: Vec<String>,
+        parent_label: String,
+        parent_scope: HashSet<String>,
+        result: Vec<Awatism>,
+    }
+
+    /// Parse the main awasm file, included files become modules whose labels and macros
+    /// are only visible to other files through `!export`
     pub fn parse_lines(
         macro_table: &mut MacroTable,
         already_included: &mut HashSet<String>,
@@ -127,367 +339,1120 @@ pub mod awasm {
         include_paths: &str,
         current_path: &str,
         lines: impl Iterator<Item = String>,
-    ) -> Vec<Instruction> {
-        let mut result = vec![];
-        let mut defining = false;
-        let mut define_str = String::new();
-        let mut cur_macro = vec![];
-
-        let mut parent_label = String::new();
-        let mut parent_scope = HashSet::<String>::new();
-
-        for line in lines {
-            let line_without_comments = line.split(';').next().unwrap_or("");
-            let trimmed = line_without_comments.trim();
-
-            if trimmed.ends_with(":") {
-                let label_name = &trimmed[..trimmed.len() - 1];
-                // local label
-                if label_name.starts_with(".") {
-                    if parent_label.len() == 0 {
-                        panic!("Local label created without parent scope");
-                    }
-                    if !parent_scope.contains(label_name) {
-                        result.push(Awatism::StrLbl(parent_label.to_string() + label_name));
-                        parent_scope.insert(label_name.to_string());
-                    } else {
-                        panic!(
-                            "Label '{}' redefined in scope '{}'",
-                            label_name, parent_label
-                        );
-                    }
-                    continue;
-                }
-                // global label
-                if !label_included.contains(label_name) {
-                    result.push(Awatism::StrLbl(label_name.to_string()));
-                    label_included.insert(label_name.to_string());
-                    // reset labels in scope
-                    parent_label = label_name.to_string();
-                    parent_scope.clear();
-                } else {
-                    panic!("Label '{}' redefined", label_name);
+    ) -> Result<Vec<Instruction>, ParseError> {
+        let file_key = canonical_key(Path::new(current_path));
+        macro_table.include_stack.push(file_key.clone());
+        let awatisms = parse_module(
+            macro_table,
+            already_included,
+            label_included,
+            include_paths,
+            current_path,
+            "",
+            lines,
+        );
+        macro_table.include_stack.pop();
+        let mut awatisms = awatisms?;
+
+        for (name, (path, span)) in &macro_table.exports {
+            if !label_included.contains(name) && macro_table.get_user_def(name).is_none() {
+                return Err(
+                    ParseError::new(format!("Exported '{}' is not defined", name), *span)
+                        .in_file(path),
+                );
+            }
+        }
+
+        let mut resolved = Vec::new();
+        for label_ref in &macro_table.label_refs {
+            let name = match resolve_label(label_ref, label_included, &macro_table.exports) {
+                Ok(Some(name)) => Ok(name),
+                Ok(None) if macro_table.relocatable => Ok(label_ref.name.clone()),
+                Ok(None) => Err(format!("Undefined label '{}'", label_ref.name)),
+                Err(err) => Err(err),
+            }
+            .map_err(|err| ParseError::new(err, label_ref.span).in_file(&label_ref.path))?;
+            resolved.push(name);
+        }
+        for awatism in &mut awatisms {
+            if let Awatism::JmpRelStr(name) | Awatism::Call(true, name) = awatism {
+                if let Some(index) = name.strip_prefix('\0') {
+                    *name = resolved[index.parse::<usize>().unwrap()].clone();
                 }
-                continue;
             }
+        }
 
-            let tokens: Vec<&str> = trimmed.splitn(2, char::is_whitespace).collect();
+        Ok(awatisms
+            .into_iter()
+            .map(|a| Instruction { awatism: a })
+            .collect())
+    }
+
+    fn parse_module(
+        macro_table: &mut MacroTable,
+        already_included: &mut HashSet<String>,
+        label_included: &mut HashSet<String>,
+        include_paths: &str,
+        current_path: &str,
+        module: &str,
+        lines: impl Iterator<Item = String>,
+    ) -> Result<Vec<Awatism>, ParseError> {
+        let lines = lines.collect::<Vec<String>>();
+        let source = lines.join("\n");
+        let tokens = lexer::tokenize(&source).map_err(|err| err.in_file(current_path))?;
+
+        let mut state = ParseState {
+            macro_table,
+            already_included,
+            label_included,
+            include_paths,
+            current_path,
+            file_key: canonical_key(Path::new(current_path)),
+            module,
+            defining: None,
+            conditions: vec![],
+            expanding: vec![],
+            lines,
+            parent_label: String::new(),
+            parent_scope: HashSet::new(),
+            result: vec![],
+        };
+
+        for line in ast::split_lines(tokens) {
+            let keep_going = state
+                .process_line(line)
+                .map_err(|err| err.in_file(current_path))?;
+            if !keep_going {
+                break;
+            }
+        }
+
+        if let Some(def) = state.defining {
+            return Err(
+                ParseError::new(format!("Missing !end for macro !{}", def.name), def.span)
+                    .in_file(current_path),
+            );
+        }
+        if let Some(condition) = state.conditions.last() {
+            return Err(ParseError::new("Missing !endif", condition.span).in_file(current_path));
+        }
+
+        Ok(state.result)
+    }
+
+    fn qualify(module: &str, name: &str) -> String {
+        if module.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}::{}", module, name)
+        }
+    }
 
-            if tokens.is_empty() || trimmed.starts_with(';') {
-                continue;
+    /// Find the label a reference points to, its own module comes first and then labels
+    /// exported under the same name, `None` when it is undefined
+    fn resolve_label(
+        label_ref: &LabelRef,
+        labels: &HashSet<String>,
+        exports: &HashMap<String, (String, Span)>,
+    ) -> Result<Option<String>, String> {
+        let name = &label_ref.name;
+        if let Some((module, _)) = name.rsplit_once("::") {
+            if !labels.contains(name) {
+                return Ok(None);
+            }
+            if module != label_ref.module && !exports.contains_key(name) {
+                return Err(format!("Label '{}' is not exported", name));
             }
+            return Ok(Some(name.to_string()));
+        }
 
-            if tokens[0] == "!once" {
-                if already_included.contains(current_path) {
-                    break;
+        let own = qualify(&label_ref.module, name);
+        if labels.contains(&own) {
+            return Ok(Some(own));
+        }
+        let suffix = format!("::{}", name);
+        let mut exported = exports
+            .keys()
+            .filter(|export| export.ends_with(&suffix) && labels.contains(*export));
+        match (exported.next(), exported.next()) {
+            (Some(export), None) => Ok(Some(export.to_string())),
+            (Some(a), Some(b)) => Err(format!(
+                "Label '{}' is ambiguous between '{}' and '{}'",
+                name, a, b
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    impl<'a> ParseState<'a> {
+        /// Returns false once `!once` stops a file that was already included
+        fn process_line(&mut self, line: Vec<Token>) -> Result<bool, ParseError> {
+            let directive = match line.first().map(|t| &t.kind) {
+                Some(TokenKind::Directive(name)) => name.as_str(),
+                _ => "",
+            };
+
+            if let Some(def) = &mut self.defining {
+                match directive {
+                    "def" => def.depth += 1,
+                    "end" if def.depth == 0 => {
+                        let def = self.defining.take().unwrap();
+                        self.macro_table
+                            .add_user_def(&def.name, def.args, def.variadic, def.lines);
+                        return Ok(true);
+                    }
+                    "end" => def.depth -= 1,
+                    _ => {}
                 }
-                continue;
+                def.lines.push(line);
+                return Ok(true);
             }
 
-            if tokens[0] == "!include" {
-                result.extend(include_file(
-                    macro_table,
-                    already_included,
-                    label_included,
-                    include_paths,
-                    tokens[1],
+            if let "if" | "else" | "endif" = directive {
+                let span = line[0].span.to(&line[line.len() - 1].span);
+                let directive = directive.to_string();
+                self.process_condition(&directive, &line[1..], span)?;
+                return Ok(true);
+            }
+            if !self.is_active() {
+                return Ok(true);
+            }
+
+            if let Some(token) = line
+                .iter()
+                .find(|t| matches!(t.kind, TokenKind::MacroArg(_)))
+            {
+                return Err(ParseError::new(
+                    format!("Macro argument {} used outside of a macro", token),
+                    token.span,
                 ));
-                continue;
             }
 
-            if tokens[0] == "!def" {
-                defining = true;
-                define_str = tokens[1].to_string();
-                continue;
+            for statement in ast::parse_line(&line)? {
+                if !self.process_statement(statement)? {
+                    return Ok(false);
+                }
             }
+            Ok(true)
+        }
 
-            if tokens[0] == "!end" {
-                defining = false;
-                let (define_name, define_args) = parse_function_call(&define_str);
-                macro_table.add_user_def(&define_name, define_args, cur_macro);
-                cur_macro = vec![];
-                continue;
+        fn process_statement(&mut self, statement: Statement) -> Result<bool, ParseError> {
+            match statement {
+                Statement::Label { name, span } => self.add_label(&name, span)?,
+                Statement::Instruction {
+                    mnemonic,
+                    arg,
+                    span,
+                } => {
+                    // label arguments are never replaced by constants
+                    let arg = match arg {
+                        Some(arg) if mnemonic != "jro" && mnemonic != "call" => {
+                            self.substitute_constants(&[arg]).pop()
+                        }
+                        arg => arg,
+                    };
+                    let awatisms = parse_instruction(&mnemonic, arg.as_ref())
+                        .map_err(|err| ParseError::new(err, span))?;
+                    self.emit(awatisms, span, None)?;
+                }
+                Statement::Directive { name, tokens, span } => {
+                    return self.process_directive(&name, &tokens, span);
+                }
             }
+            Ok(true)
+        }
 
-            if defining {
-                cur_macro.push(trimmed.to_string());
-                continue;
+        fn add_label(&mut self, label_name: &str, span: Span) -> Result<(), ParseError> {
+            // macro-local label, already made unique by the expansion
+            if label_name.starts_with('@') {
+                check_label_ref(label_name).map_err(|err| ParseError::new(err, span))?;
+                if !self.label_included.insert(label_name.to_string()) {
+                    return Err(ParseError::new(
+                        format!("Label '{}' redefined", label_name),
+                        span,
+                    ));
+                }
+                self.push(Awatism::StrLbl(label_name.to_string()), span, None);
+                return Ok(());
+            }
+            // local label
+            if label_name.starts_with('.') {
+                if self.parent_label.is_empty() {
+                    return Err(ParseError::new(
+                        "Local label created without parent scope",
+                        span,
+                    ));
+                }
+                if self.parent_scope.contains(label_name) {
+                    return Err(ParseError::new(
+                        format!(
+                            "Label '{}' redefined in scope '{}'",
+                            label_name, self.parent_label
+                        ),
+                        span,
+                    ));
+                }
+                let label = self.parent_label.to_string() + label_name;
+                self.label_included.insert(label.clone());
+                self.push(Awatism::StrLbl(label), span, None);
+                self.parent_scope.insert(label_name.to_string());
+                return Ok(());
+            }
+            // module label
+            if label_name.contains("::") {
+                return Err(ParseError::new(
+                    format!("Label '{}' cannot be qualified", label_name),
+                    span,
+                ));
+            }
+            let label = qualify(self.module, label_name);
+            if self.label_included.contains(&label) {
+                return Err(ParseError::new(
+                    format!("Label '{}' redefined", label),
+                    span,
+                ));
             }
+            self.push(Awatism::StrLbl(label.clone()), span, None);
+            self.label_included.insert(label.clone());
+            // reset labels in scope
+            self.parent_label = label;
+            self.parent_scope.clear();
+            Ok(())
+        }
 
-            result.extend(parse_line(&macro_table, &tokens));
+        /// Append an awatism and record where it came from
+        fn push(&mut self, awatism: Awatism, span: Span, builtin: Option<&str>) {
+            if let Some(origins) = &mut self.macro_table.origins {
+                // expanded lines are listed at the outermost call
+                let line = self.expanding.first().map_or(span, |(_, call)| *call).line;
+                let mut macros: Vec<String> = self
+                    .expanding
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                macros.extend(builtin.map(|name| name.to_string()));
+                origins.push(Origin {
+                    path: self.current_path.to_string(),
+                    line,
+                    text: line
+                        .checked_sub(1)
+                        .and_then(|i| self.lines.get(i))
+                        .cloned()
+                        .unwrap_or_default(),
+                    macros,
+                });
+            }
+            self.result.push(awatism);
         }
 
-        result
-            .into_iter()
-            .map(|a| Instruction { awatism: a })
-            .collect()
-    }
+        /// Append awatisms, label references are recorded to be resolved at the end
+        fn emit(
+            &mut self,
+            awatisms: Vec<Awatism>,
+            span: Span,
+            builtin: Option<&str>,
+        ) -> Result<(), ParseError> {
+            for mut awatism in awatisms {
+                if let Awatism::JmpRelStr(name) | Awatism::Call(true, name) = &mut awatism {
+                    if name.starts_with('.') {
+                        if self.parent_label.is_empty() {
+                            return Err(ParseError::new(
+                                "Local label referenced without parent scope",
+                                span,
+                            ));
+                        }
+                        *name = self.parent_label.to_string() + name;
+                    } else if !name.starts_with('@') {
+                        self.macro_table.label_refs.push(LabelRef {
+                            name: name.to_string(),
+                            module: self.module.to_string(),
+                            path: self.current_path.to_string(),
+                            span,
+                        });
+                        *name = format!("\0{}", self.macro_table.label_refs.len() - 1);
+                    }
+                }
+                self.push(awatism, span, builtin);
+            }
+            Ok(())
+        }
 
-    fn parse_line(macro_table: &MacroTable, tokens: &Vec<&str>) -> Vec<Awatism> {
-        // is macro
-        if tokens[0].starts_with("!") {
-            if macro_table.is_builtin(tokens[0]) {
-                if let Some(process_fn) = macro_table.get_builtin(tokens[0]) {
-                    return process_fn(tokens[1]).get_vec();
+        /// Key of the user macro called as `name`, its own module comes first and then
+        /// macros exported under the same name
+        fn find_macro(&self, name: &str) -> Result<Option<String>, String> {
+            if let Some((module, _)) = name.rsplit_once("::") {
+                if self.macro_table.get_user_def(name).is_none() {
+                    return Ok(None);
                 }
-            } else {
-                if let Some(_macro) = macro_table.get_user_def(&tokens[0][1..]) {
-                    let args: Vec<&str> = tokens[1].split(',').collect();
-                    if _macro.args.len() != args.len() {
-                        panic!(
-                            "Macro !{} expected {} arguments, received {}",
-                            &tokens[0][1..],
-                            _macro.args.len(),
-                            args.len()
-                        );
+                if module != self.module && !self.macro_table.exports.contains_key(name) {
+                    return Err(format!("Macro !{} is not exported", name));
+                }
+                return Ok(Some(name.to_string()));
+            }
+
+            let own = qualify(self.module, name);
+            if self.macro_table.get_user_def(&own).is_some() {
+                return Ok(Some(own));
+            }
+            let suffix = format!("::{}", name);
+            let mut exported = self.macro_table.exports.keys().filter(|export| {
+                export.ends_with(&suffix) && self.macro_table.get_user_def(export).is_some()
+            });
+            match (exported.next(), exported.next()) {
+                (Some(export), None) => Ok(Some(export.to_string())),
+                (Some(a), Some(b)) => Err(format!(
+                    "Macro !{} is ambiguous between !{} and !{}",
+                    name, a, b
+                )),
+                _ => Ok(None),
+            }
+        }
+
+        fn process_directive(
+            &mut self,
+            name: &str,
+            tokens: &[Token],
+            span: Span,
+        ) -> Result<bool, ParseError> {
+            match name {
+                "once" => {
+                    return Ok(!self.already_included.contains(&self.file_key));
+                }
+                "include" => {
+                    let (path, relative) = match tokens {
+                        [Token {
+                            kind: TokenKind::Path(path),
+                            ..
+                        }] => (path, false),
+                        [Token {
+                            kind:
+                                TokenKind::Str {
+                                    awascii: false,
+                                    raw,
+                                },
+                            ..
+                        }] => (raw, true),
+                        _ => {
+                            return Err(ParseError::new(
+                                "Expected !include <path> or !include \"path\"",
+                                span,
+                            ))
+                        }
+                    };
+                    let awatisms = include_file(
+                        self.macro_table,
+                        self.already_included,
+                        self.label_included,
+                        self.include_paths,
+                        self.current_path,
+                        path,
+                        relative,
+                    )
+                    .map_err(|err| err.in_file(self.current_path))?;
+                    // an error inside the included file keeps its own path
+                    let awatisms = awatisms.map_err(|err| ParseError::new(err, span))?;
+                    self.result.extend(awatisms);
+                }
+                "export" => {
+                    for arg in ast::split_args(tokens) {
+                        match arg {
+                            [Token {
+                                kind: TokenKind::Ident(export),
+                                ..
+                            }] if !export.contains("::") => {
+                                // everything in the main file is already visible to it
+                                if !self.module.is_empty() {
+                                    self.macro_table.exports.insert(
+                                        qualify(self.module, export),
+                                        (self.current_path.to_string(), span),
+                                    );
+                                }
+                            }
+                            _ => return Err(ParseError::new("Expected !export name, ...", span)),
+                        }
                     }
-                    return expand_macro(macro_table, _macro, args);
-                } else {
-                    panic!("Macro !{} not found", &tokens[0][1..]);
-                }
-            }
-        }
-
-        let awatism = match tokens[0] {
-            "nop" if tokens.len() == 1 => vec![Awatism::Nop],
-            "prn" if tokens.len() == 1 => vec![Awatism::Prn],
-            "pr1" if tokens.len() == 1 => vec![Awatism::Pr1],
-            "red" if tokens.len() == 1 => vec![Awatism::Red],
-            "r3d" if tokens.len() == 1 => vec![Awatism::R3d],
-            "blo" if tokens.len() == 2 => {
-                let b: i32 = tokens[1].parse().ok().unwrap();
-                vec![Awatism::Blo(b as u8)]
-            }
-            "sbm" if tokens.len() == 2 => {
-                let b: i32 = tokens[1].parse().ok().unwrap();
-                vec![Awatism::Sbm(b as u8)]
-            }
-            "pop" if tokens.len() == 1 => vec![Awatism::Pop],
-            "dpl" if tokens.len() == 1 => vec![Awatism::Dpl],
-            "srn" if tokens.len() == 2 => {
-                let b: i32 = tokens[1].parse().ok().unwrap();
-                vec![Awatism::Srn(b as u8)]
-            }
-            "mrg" if tokens.len() == 1 => vec![Awatism::Mrg],
-            "4dd" if tokens.len() == 1 => vec![Awatism::Add],
-            "sub" if tokens.len() == 1 => vec![Awatism::Sub],
-            "mul" if tokens.len() == 1 => vec![Awatism::Mul],
-            "div" if tokens.len() == 1 => vec![Awatism::Div],
-            "cnt" if tokens.len() == 1 => vec![Awatism::Cnt],
-            "lbl" if tokens.len() == 2 => {
-                let b: i32 = tokens[1].parse().ok().unwrap();
-                vec![Awatism::Lbl(b as u8)]
-            }
-            "jmp" if tokens.len() == 2 => {
-                let b: i32 = tokens[1].parse().ok().unwrap();
-                vec![Awatism::Jmp(b as u8)]
-            }
-            "jro" => {
-                if tokens.len() == 2 {
-                    let is_number = tokens[1].parse::<i32>().is_ok();
-                    if is_number {
-                        let b: i32 = tokens[1].parse().ok().unwrap();
-                        vec![Awatism::Jmp(b as u8)]
-                    } else {
-                        vec![Awatism::JmpRelStr(tokens[1].to_string())]
+                }
+                "def" => {
+                    let (macro_name, args, variadic) = parse_macro_signature(tokens, span)?;
+                    if macro_name.contains("::") {
+                        return Err(ParseError::new(
+                            format!("Macro !{} cannot be qualified", macro_name),
+                            span,
+                        ));
                     }
-                } else if tokens.len() == 1 {
-                    vec![Awatism::JmpRel]
-                } else {
-                    panic!("jro instruction received more than 2 tokens")
-                }
-            }
-            "eql" if tokens.len() == 1 => vec![Awatism::Eql],
-            "lss" if tokens.len() == 1 => vec![Awatism::Lss],
-            "gr8" if tokens.len() == 1 => vec![Awatism::Gr8],
-            "lib" if tokens.len() == 1 => vec![Awatism::Lib],
-            "call" => {
-                if tokens.len() == 2 {
-                    let is_number = tokens[1].parse::<i32>().is_ok();
-                    if is_number {
-                        panic!("call instruction did not receive a string label");
+                    self.defining = Some(MacroDef {
+                        name: qualify(self.module, &macro_name),
+                        args,
+                        variadic,
+                        lines: vec![],
+                        span,
+                        depth: 0,
+                    });
+                }
+                "end" => return Err(ParseError::new("!end without !def", span)),
+                "set" => {
+                    let (constant, expr) = match ast::split_args(tokens)[..] {
+                        [[Token {
+                            kind: TokenKind::Ident(constant),
+                            ..
+                        }], expr] => (constant.clone(), expr),
+                        _ => return Err(ParseError::new("Expected !set name, value", span)),
+                    };
+                    let value = eval_expr(expr, &self.macro_table.constants)
+                        .map_err(|err| ParseError::new(err, span))?;
+                    self.macro_table.constants.insert(constant, value);
+                }
+                _ => {
+                    let tokens = self.substitute_constants(tokens);
+                    if let Some(process_fn) = self.macro_table.get_builtin(name) {
+                        let res = process_fn(&tokens)
+                            .map_err(|err| ParseError::new(format!("!{}: {}", name, err), span))?;
+                        self.emit(res.get_vec(), span, Some(name))?;
+                    } else if let Some(key) = self
+                        .find_macro(name)
+                        .map_err(|err| ParseError::new(err, span))?
+                    {
+                        if self.expanding.len() >= MAX_MACRO_DEPTH {
+                            return Err(ParseError::new(
+                                format!(
+                                    "Macro !{} nested deeper than {} expansions",
+                                    name, MAX_MACRO_DEPTH
+                                ),
+                                span,
+                            ));
+                        }
+                        self.macro_table.expansions += 1;
+                        let id = self.macro_table.expansions;
+                        let _macro = self.macro_table.get_user_def(&key).unwrap();
+                        let lines = expand_macro(name, _macro, &tokens, span, id)?;
+
+                        let conditions = self.conditions.len();
+                        self.expanding.push((name.to_string(), span));
+                        for line in lines {
+                            if !self.process_line(line)? {
+                                self.expanding.pop();
+                                return Ok(false);
+                            }
+                        }
+                        self.expanding.pop();
+                        if self.conditions.len() != conditions {
+                            return Err(ParseError::new(
+                                format!("Unbalanced !if and !endif in macro !{}", name),
+                                span,
+                            ));
+                        }
                     } else {
-                        vec![Awatism::Call(true, tokens[1].to_string())]
+                        return Err(ParseError::new(format!("Macro !{} not found", name), span));
                     }
-                } else if tokens.len() == 1 {
-                    vec![Awatism::Call(false, "".to_string())]
-                } else {
-                    panic!("call instruction received more than 2 tokens");
                 }
             }
-            "ret" if tokens.len() == 1 => vec![Awatism::Ret],
-            "trm" if tokens.len() == 1 => vec![Awatism::Trm],
-            _ => vec![],
+            Ok(true)
+        }
+
+        fn is_active(&self) -> bool {
+            self.conditions
+                .last()
+                .is_none_or(|c| c.parent_active && c.value != c.in_else)
+        }
+
+        fn process_condition(
+            &mut self,
+            name: &str,
+            tokens: &[Token],
+            span: Span,
+        ) -> Result<(), ParseError> {
+            match name {
+                "if" => {
+                    let parent_active = self.is_active();
+                    // conditions in skipped blocks may refer to constants that are never set
+                    let value = parent_active
+                        && eval_expr(tokens, &self.macro_table.constants)
+                            .map_err(|err| ParseError::new(err, span))?
+                            != 0;
+                    self.conditions.push(Condition {
+                        value,
+                        parent_active,
+                        in_else: false,
+                        span,
+                    });
+                }
+                "else" | "endif" if !tokens.is_empty() => {
+                    return Err(ParseError::new(
+                        format!("!{} does not take arguments", name),
+                        span,
+                    ));
+                }
+                "else" => match self.conditions.last_mut() {
+                    Some(condition) if !condition.in_else => condition.in_else = true,
+                    Some(_) => return Err(ParseError::new("Duplicate !else", span)),
+                    None => return Err(ParseError::new("!else without !if", span)),
+                },
+                _ => {
+                    if self.conditions.pop().is_none() {
+                        return Err(ParseError::new("!endif without !if", span));
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Replace identifiers naming a `!set` constant with its value
+        fn substitute_constants(&self, tokens: &[Token]) -> Vec<Token> {
+            tokens
+                .iter()
+                .map(|token| match &token.kind {
+                    TokenKind::Ident(name) => match self.macro_table.constants.get(name) {
+                        Some(value) => Token {
+                            kind: TokenKind::Number(value.to_string()),
+                            span: token.span,
+                        },
+                        None => token.clone(),
+                    },
+                    _ => token.clone(),
+                })
+                .collect()
+        }
+    }
+
+    /// Evaluate a constant expression left to right, operators are `+ - * / %` and the
+    /// comparisons `eq ne lt le gt ge` which give 1 or 0
+    fn eval_expr(tokens: &[Token], constants: &HashMap<String, i64>) -> Result<i64, String> {
+        let operand = |token: Option<&Token>| -> Result<i64, String> {
+            let token = token.ok_or("Expected a value")?;
+            match &token.kind {
+                TokenKind::Number(number) => {
+                    lexer::parse_int(number).ok_or(format!("Invalid integer '{}'", number))
+                }
+                TokenKind::Chr { awascii, raw } => match lexer::unescape(raw, *awascii)?[..] {
+                    [byte] => Ok(byte as i64),
+                    _ => Err(format!("Expected a single character, found {}", token)),
+                },
+                TokenKind::Ident(name) => constants
+                    .get(name)
+                    .copied()
+                    .ok_or(format!("Unknown constant '{}'", name)),
+                _ => Err(format!("Expected a value, found '{}'", token)),
+            }
         };
 
-        awatism
+        let mut tokens = tokens.iter();
+        let mut value = operand(tokens.next())?;
+        while let Some(op) = tokens.next() {
+            let rhs = operand(tokens.next())?;
+            let op = match &op.kind {
+                TokenKind::Ident(op) => op.as_str(),
+                _ => "",
+            };
+            value = match op {
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err("Division by zero".to_string()),
+                "/" => value.wrapping_div(rhs),
+                "%" => value.wrapping_rem(rhs),
+                "eq" => (value == rhs) as i64,
+                "ne" => (value != rhs) as i64,
+                "lt" => (value < rhs) as i64,
+                "le" => (value <= rhs) as i64,
+                "gt" => (value > rhs) as i64,
+                "ge" => (value >= rhs) as i64,
+                _ => return Err(format!("Unknown operator '{}'", op)),
+            };
+        }
+        Ok(value)
     }
 
-    fn parse_include_paths(include_paths: &str) -> Vec<&str> {
-        include_paths.split(';').collect()
+    /// Macro-local labels only exist once a macro expansion has given them a unique suffix
+    fn check_label_ref(label: &str) -> Result<(), String> {
+        if label.starts_with('@') && !label.contains('#') {
+            return Err(format!(
+                "Macro-local label '{}' used outside of a macro",
+                label
+            ));
+        }
+        Ok(())
     }
 
-    fn find_and_read_file(relative_path: &str, paths: &[&str]) -> io::Result<Option<Vec<String>>> {
-        for path in paths {
-            let full_path = Path::new(path).join(relative_path);
-            if full_path.exists() {
-                let file = File::open(full_path)?;
-                let lines: Vec<String> = io::BufReader::new(file)
-                    .lines()
-                    .filter_map(Result::ok)
-                    .collect();
-                return Ok(Some(lines));
+    /// `name` or `name(arg, ..., rest...)` following `!def`
+    fn parse_macro_signature(
+        tokens: &[Token],
+        span: Span,
+    ) -> Result<(String, Vec<String>, bool), ParseError> {
+        let name = match tokens.first().map(|t| &t.kind) {
+            Some(TokenKind::Ident(name)) => name.clone(),
+            _ => return Err(ParseError::new("Expected a macro name after !def", span)),
+        };
+
+        let mut args: Vec<String> = vec![];
+        let mut variadic = false;
+        match tokens.get(1).map(|t| &t.kind) {
+            None => return Ok((name, args, variadic)),
+            Some(TokenKind::LParen) => {}
+            Some(_) => return Err(ParseError::new("Missing opening \"(\"", tokens[1].span)),
+        }
+        if tokens.last().map(|t| &t.kind) != Some(&TokenKind::RParen) {
+            return Err(ParseError::new("Missing closing \")\"", span));
+        }
+
+        for arg in ast::split_args(&tokens[2..tokens.len() - 1]) {
+            if variadic {
+                return Err(ParseError::new(
+                    "Only the last macro argument can be variadic",
+                    span,
+                ));
+            }
+            match arg {
+                [] if args.is_empty() => {}
+                [Token {
+                    kind: TokenKind::Ident(arg),
+                    ..
+                }] => match arg.strip_suffix("...") {
+                    Some(arg) => {
+                        args.push(arg.to_string());
+                        variadic = true;
+                    }
+                    None => args.push(arg.clone()),
+                },
+                _ => return Err(ParseError::new("Expected macro argument names", span)),
             }
         }
-        Ok(None)
+        Ok((name, args, variadic))
+    }
+
+    /// Argument of the awatism with opcode `op`, checked against the width of its field
+    fn arg_value(op: u8, token: Option<&Token>) -> Result<u8, String> {
+        let value = match token.map(|t| &t.kind) {
+            Some(TokenKind::Number(number)) => {
+                lexer::parse_int(number).ok_or_else(|| format!("Invalid integer '{}'", number))?
+            }
+            Some(TokenKind::Chr { awascii, raw }) => match lexer::unescape(raw, *awascii)?[..] {
+                [byte] => byte as i64,
+                _ => {
+                    return Err(format!(
+                        "Expected a single character, found {}",
+                        token.unwrap()
+                    ))
+                }
+            },
+            Some(_) => return Err(format!("Expected a number, found '{}'", token.unwrap())),
+            None => return Err("Expected a number".to_string()),
+        };
+
+        let bits = Awatism::arg_bits(op);
+        // blo is the only signed argument
+        let (min, max) = if op == 0x05 {
+            (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+        } else {
+            (0, (1 << bits) - 1)
+        };
+        if value < min || value > max {
+            return Err(format!(
+                "Argument {} does not fit in {} bits, expected {} to {}",
+                token.unwrap(),
+                bits,
+                min,
+                max
+            ));
+        }
+        Ok(value as u8)
+    }
+
+    fn parse_instruction(mnemonic: &str, arg: Option<&Token>) -> Result<Vec<Awatism>, String> {
+        let has_arg = arg.is_some();
+        if let Some(TokenKind::Ident(label)) = arg.map(|t| &t.kind) {
+            check_label_ref(label)?;
+        }
+        let awatism = match mnemonic {
+            "nop" if !has_arg => vec![Awatism::Nop],
+            "prn" if !has_arg => vec![Awatism::Prn],
+            "pr1" if !has_arg => vec![Awatism::Pr1],
+            "red" if !has_arg => vec![Awatism::Red],
+            "r3d" if !has_arg => vec![Awatism::R3d],
+            "blo" => vec![Awatism::Blo(arg_value(0x05, arg)?)],
+            "sbm" => vec![Awatism::Sbm(arg_value(0x06, arg)?)],
+            "pop" if !has_arg => vec![Awatism::Pop],
+            "dpl" if !has_arg => vec![Awatism::Dpl],
+            "srn" => vec![Awatism::Srn(arg_value(0x09, arg)?)],
+            "mrg" if !has_arg => vec![Awatism::Mrg],
+            "4dd" if !has_arg => vec![Awatism::Add],
+            "sub" if !has_arg => vec![Awatism::Sub],
+            "mul" if !has_arg => vec![Awatism::Mul],
+            "div" if !has_arg => vec![Awatism::Div],
+            "cnt" if !has_arg => vec![Awatism::Cnt],
+            "lbl" => vec![Awatism::Lbl(arg_value(0x10, arg)?)],
+            "jmp" => vec![Awatism::Jmp(arg_value(0x11, arg)?)],
+            "jro" => match arg.map(|t| &t.kind) {
+                Some(TokenKind::Number(_) | TokenKind::Chr { .. }) => {
+                    vec![Awatism::Jmp(arg_value(0x11, arg)?)]
+                }
+                Some(TokenKind::Ident(label)) => vec![Awatism::JmpRelStr(label.to_string())],
+                Some(_) => return Err(format!("Expected a label, found '{}'", arg.unwrap())),
+                None => vec![Awatism::JmpRel],
+            },
+            "eql" if !has_arg => vec![Awatism::Eql],
+            "lss" if !has_arg => vec![Awatism::Lss],
+            "gr8" if !has_arg => vec![Awatism::Gr8],
+            "sys" if !has_arg => vec![Awatism::Syscall],
+            "p0p" if !has_arg => vec![Awatism::DoublePop],
+            "lib" if !has_arg => vec![Awatism::Lib],
+            "call" => match arg.map(|t| &t.kind) {
+                Some(TokenKind::Ident(label)) => vec![Awatism::Call(true, label.to_string())],
+                Some(_) => {
+                    return Err("call instruction did not receive a string label".to_string())
+                }
+                None => vec![Awatism::Call(false, "".to_string())],
+            },
+            "ret" if !has_arg => vec![Awatism::Ret],
+            "trm" if !has_arg => vec![Awatism::Trm],
+            "nop" | "prn" | "pr1" | "red" | "r3d" | "pop" | "dpl" | "mrg" | "4dd" | "sub"
+            | "mul" | "div" | "cnt" | "eql" | "lss" | "gr8" | "sys" | "p0p" | "lib" | "ret"
+            | "trm" => return Err(format!("'{}' does not take an argument", mnemonic)),
+            _ => return Err(format!("Unknown instruction '{}'", mnemonic)),
+        };
+
+        Ok(awatism)
+    }
+
+    fn parse_include_paths(include_paths: &str) -> Vec<&str> {
+        include_paths.split(';').collect()
     }
 
+    fn find_file(relative_path: &str, paths: &[&str]) -> Option<PathBuf> {
+        paths
+            .iter()
+            .map(|path| Path::new(path).join(relative_path))
+            .find(|full_path| full_path.exists())
+    }
+
+    fn read_file_lines(path: &Path) -> io::Result<Vec<String>> {
+        let file = File::open(path)?;
+        io::BufReader::new(file).lines().collect()
+    }
+
+    /// Key identifying a file however its path is spelled, falls back to the path itself
+    fn canonical_key(path: &Path) -> String {
+        path.canonicalize()
+            .unwrap_or_else(|_| path.to_path_buf())
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Include `<path>` from the include paths or `"path"` relative to the including file
+    ///
+    /// Outer error comes from parsing the included file, inner error is about the include itself
     fn include_file(
         macro_table: &mut MacroTable,
         already_included: &mut HashSet<String>,
         label_included: &mut HashSet<String>,
         include_paths: &str,
-        path_str: &str,
-    ) -> Vec<Awatism> {
-        let replaced_string = path_str.replace("\\n", "\n");
-        let path = replaced_string.trim();
+        current_path: &str,
+        path: &str,
+        relative: bool,
+    ) -> Result<Result<Vec<Awatism>, String>, ParseError> {
+        let path = path.trim();
+        let display = if relative {
+            format!("\"{}\"", path)
+        } else {
+            format!("<{}>", path)
+        };
 
-        if path.chars().nth(0).unwrap() != '<' {
-            panic!("Missing opening \"<\"");
+        // search path includes must stay inside the include directories
+        if !relative
+            && !Path::new(path)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Ok(Err(format!(
+                "Include path {} escapes the include directories",
+                display
+            )));
         }
-        if path.len() == 1 || path.chars().nth_back(0).unwrap() != '>' {
-            panic!("Missing closing \">\"");
+
+        let full_path = if relative {
+            let dir = Path::new(current_path).parent().unwrap_or(Path::new(""));
+            Some(dir.join(path)).filter(|full_path| full_path.exists())
+        } else {
+            find_file(path, &parse_include_paths(include_paths))
+        };
+        let full_path = match full_path {
+            Some(full_path) => full_path,
+            None => return Ok(Err(format!("Awasm source file {} not found", display))),
+        };
+        let lines = match read_file_lines(&full_path) {
+            Ok(lines) => lines,
+            Err(err) => return Ok(Err(format!("Could not read {}: {}", display, err))),
+        };
+
+        let file_key = canonical_key(&full_path);
+        // both forms must stay inside the directories allowed by the policy
+        if let Some(include_dirs) = &macro_table.include_dirs {
+            if !include_dirs
+                .iter()
+                .any(|dir| Path::new(&file_key).starts_with(dir))
+            {
+                return Ok(Err(format!(
+                    "Include path {} is outside the allowed include directories",
+                    display
+                )));
+            }
         }
-        let path = &path[1..path.len() - 1];
 
-        let paths = parse_include_paths(include_paths);
+        if let Some(start) = macro_table
+            .include_stack
+            .iter()
+            .position(|key| *key == file_key)
+        {
+            let mut cycle = macro_table.include_stack[start..].to_vec();
+            cycle.push(file_key);
+            return Ok(Err(format!("Include cycle {}", cycle.join(" -> "))));
+        }
 
-        if let Some(lines) = find_and_read_file(path, &paths).unwrap() {
-            let instructions = parse_lines(
-                macro_table,
-                already_included,
-                label_included,
-                include_paths,
-                path,
-                lines.into_iter(),
-            );
-            already_included.insert(path.to_string());
-            instructions.into_iter().map(|i| i.awatism).collect()
-        } else {
-            panic!("Awasm source file {} not found", path);
+        // modules are named after the file stem, `lib/math.awasm` is `math`
+        let module: String = full_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("")
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        match macro_table.modules.get(&module) {
+            Some(other) if *other != file_key => {
+                return Ok(Err(format!(
+                    "Module '{}' of {} is already defined by {}",
+                    module, display, other
+                )))
+            }
+            _ => {
+                macro_table.modules.insert(module.clone(), file_key.clone());
+            }
         }
+
+        macro_table.include_stack.push(file_key.clone());
+        let awatisms = parse_module(
+            macro_table,
+            already_included,
+            label_included,
+            include_paths,
+            &full_path.to_string_lossy(),
+            &module,
+            lines.into_iter(),
+        );
+        macro_table.include_stack.pop();
+        already_included.insert(file_key);
+        Ok(Ok(awatisms?))
     }
 
-    fn expand_macro(macro_table: &MacroTable, _macro: &UserMacro, args: Vec<&str>) -> Vec<Awatism> {
-        let mut res = vec![];
+    /// Substitute `$arg` tokens in the macro body with the call arguments and give
+    /// macro-local `@label`s the unique suffix `#id`
+    ///
+    /// `$rest` of a variadic argument `rest...` expands to the remaining arguments separated
+    /// by commas and `$#` to their count. Bodies of nested `!def`s keep their own arguments
+    /// and labels until they are expanded themselves
+    fn expand_macro(
+        name: &str,
+        _macro: &UserMacro,
+        tokens: &[Token],
+        span: Span,
+        id: usize,
+    ) -> Result<Vec<Vec<Token>>, ParseError> {
+        let args = ast::split_args(tokens);
+        let fixed = _macro.args.len() - _macro.variadic as usize;
+        if args.len() < fixed || (!_macro.variadic && args.len() > fixed) {
+            return Err(ParseError::new(
+                format!(
+                    "Macro !{} expected {}{} arguments, received {}",
+                    name,
+                    if _macro.variadic { "at least " } else { "" },
+                    fixed,
+                    args.len()
+                ),
+                span,
+            ));
+        }
+        let rest = &args[fixed.min(args.len())..];
+
+        let mut lines = vec![];
+        let mut depth = 0;
         for line in &_macro.lines {
-            let mut expanded_line = line.to_string();
-            for i in 0..args.len() {
-                let arg_name = &_macro.args[i];
-                let arg_val = args[i].trim();
-                let placeholder = format!("${}", arg_name);
-                expanded_line = expanded_line.replace(&placeholder, arg_val);
+            match line.first().map(|t| &t.kind) {
+                Some(TokenKind::Directive(directive)) if directive == "end" && depth > 0 => {
+                    depth -= 1
+                }
+                _ => {}
             }
 
-            let line_without_comments = expanded_line.split(';').next().unwrap_or("");
-            let trimmed = line_without_comments.trim();
-            let tokens: Vec<&str> = trimmed.splitn(2, char::is_whitespace).collect();
-            res.extend(parse_line(macro_table, &tokens));
+            let mut expanded_line = vec![];
+            for token in line {
+                match &token.kind {
+                    TokenKind::MacroArg(arg_name) if arg_name == "#" && depth == 0 => {
+                        expanded_line.push(Token {
+                            kind: TokenKind::Number(rest.len().to_string()),
+                            span: token.span,
+                        });
+                    }
+                    TokenKind::MacroArg(arg_name) => {
+                        match _macro.args.iter().position(|a| a == arg_name) {
+                            Some(i) if _macro.variadic && i == fixed => {
+                                for (j, arg) in rest.iter().enumerate() {
+                                    if j > 0 {
+                                        expanded_line.push(Token {
+                                            kind: TokenKind::Comma,
+                                            span: token.span,
+                                        });
+                                    }
+                                    expanded_line.extend(arg.iter().cloned());
+                                }
+                            }
+                            Some(i) => expanded_line.extend(args[i].iter().cloned()),
+                            None if depth > 0 => expanded_line.push(token.clone()),
+                            None => {
+                                return Err(ParseError::new(
+                                    format!("Macro !{} has no argument ${}", name, arg_name),
+                                    token.span,
+                                ))
+                            }
+                        }
+                    }
+                    TokenKind::Label(label) if label.starts_with('@') && depth == 0 => {
+                        expanded_line.push(Token {
+                            kind: TokenKind::Label(format!("{}#{}", label, id)),
+                            span: token.span,
+                        });
+                    }
+                    TokenKind::Ident(label) if label.starts_with('@') && depth == 0 => {
+                        expanded_line.push(Token {
+                            kind: TokenKind::Ident(format!("{}#{}", label, id)),
+                            span: token.span,
+                        });
+                    }
+                    _ => expanded_line.push(token.clone()),
+                }
+            }
+
+            if let Some(TokenKind::Directive(directive)) = line.first().map(|t| &t.kind) {
+                if directive == "def" {
+                    depth += 1;
+                }
+            }
+            lines.push(expanded_line);
         }
+        Ok(lines)
+    }
+
+    fn single(tokens: &[Token]) -> Result<&Token, String> {
+        match tokens {
+            [token] => Ok(token),
+            _ => Err("Expected a single argument".to_string()),
+        }
+    }
+
+    /// Push bytes in order and merge them into a double bubble, like `!i32`
+    fn push_bytes(bytes: &[u8]) -> Vec<Awatism> {
+        let mut res: Vec<Awatism> = bytes.iter().map(|byte| Awatism::Blo(*byte)).collect();
+        res.push(Awatism::Srn(bytes.len() as u8));
         res
     }
 
-    fn process_i32(token: &str) -> MacroResult {
-        let value = token.parse().ok().unwrap();
+    /// Wrap a value in a `lib` argument with its type tag, the tags are for ascii and
+    /// AWA-SCII values
+    fn tag_arg((ascii, awascii): (u8, u8), value: MacroResult) -> MacroResult {
+        let (value, is_awascii) = value.get_vec_and_bool();
+        let mut res = vec![Awatism::Blo(if is_awascii { awascii } else { ascii })];
+        res.extend(value);
+        res.push(Awatism::Srn(2));
+        MacroResult::VecAndBool(res, is_awascii)
+    }
+
+    /// Argument of a `lib` call tagged by its literal, integers as i32 and decimals as f32
+    fn lib_arg(token: &Token) -> Result<MacroResult, String> {
+        let res = match &token.kind {
+            TokenKind::Number(number) if lexer::parse_int(number).is_some() => {
+                tag_arg((0x0, 0x0), process_i32(token)?)
+            }
+            TokenKind::Number(_) => tag_arg((0x0, 0x0), process_f32(token)?),
+            TokenKind::Chr { .. } => tag_arg((0x2, 0x1), process_chr(token)?),
+            TokenKind::Str { .. } => tag_arg((0x4, 0x3), process_str(token)?),
+            _ => return Err(format!("Expected a literal, found '{}'", token)),
+        };
+        Ok(res)
+    }
+
+    /// Compare the top two bubbles and jump if the comparison holds, otherwise pop both
+    fn branch(compare: Awatism, token: &Token) -> Result<MacroResult, String> {
+        let label = match &token.kind {
+            TokenKind::Ident(label) => label,
+            _ => return Err(format!("Expected a label, found '{}'", token)),
+        };
+        check_label_ref(label)?;
+        Ok(MacroResult::VecOnly(vec![
+            compare,
+            Awatism::JmpRelStr(label.to_string()),
+            Awatism::Pop,
+            Awatism::Pop,
+        ]))
+    }
+
+    fn process_i32(token: &Token) -> Result<MacroResult, String> {
+        let value: i32 = match &token.kind {
+            TokenKind::Number(number) => lexer::parse_int(number)
+                .and_then(|value| i32::try_from(value).ok())
+                .ok_or_else(|| format!("Invalid i32 '{}'", number))?,
+            _ => return Err(format!("Expected an i32, found '{}'", token)),
+        };
         let mut res = Vec::new();
         for byte in i32::to_le_bytes(value) {
             res.push(Awatism::Blo(byte));
         }
         res.push(Awatism::Srn(4));
-        MacroResult::VecOnly(res)
+        Ok(MacroResult::VecOnly(res))
     }
 
-    fn process_f32(token: &str) -> MacroResult {
-        let value = token.parse().ok().unwrap();
+    fn process_f32(token: &Token) -> Result<MacroResult, String> {
+        let value: f32 = match &token.kind {
+            TokenKind::Number(number) => number
+                .parse()
+                .map_err(|_| format!("Invalid f32 '{}'", number))?,
+            _ => return Err(format!("Expected an f32, found '{}'", token)),
+        };
         let mut res = Vec::new();
         for byte in f32::to_le_bytes(value) {
             res.push(Awatism::Blo(byte));
         }
         res.push(Awatism::Srn(4));
-        MacroResult::VecOnly(res)
+        Ok(MacroResult::VecOnly(res))
     }
 
-    fn process_chr(string_content: &str) -> MacroResult {
-        let mut res = Vec::new();
-        let replaced_string = string_content.replace("\\n", "\n");
-        let mut string_content = replaced_string.trim();
-
-        let mut awascii = false;
-        if string_content.chars().nth(0).unwrap() == 'a' {
-            string_content = &string_content[1..];
-            awascii = true;
-        }
-
-        if string_content.chars().nth(0).unwrap() != '\'' {
-            panic!("Missing opening \"'\"");
-        }
-        if string_content.len() == 1 || string_content.chars().nth_back(0).unwrap() != '\'' {
-            panic!("Missing closing \"'\"");
-        }
-
-        if string_content.len() > 3 {
-            panic!("!char expects a single character")
-        }
-
-        let string_content = &string_content[1..string_content.len() - 1];
-        let c = string_content.chars().nth(0).unwrap();
+    fn process_chr(token: &Token) -> Result<MacroResult, String> {
+        let (awascii, raw) = match &token.kind {
+            TokenKind::Chr { awascii, raw } => (*awascii, raw),
+            _ => return Err(format!("Expected a char literal, found '{}'", token)),
+        };
 
-        if awascii {
-            res.push(Awatism::Blo(AWA_SCII.find(c).unwrap() as u8));
-        } else {
-            res.push(Awatism::Blo(c as u8));
-        }
+        let byte = match lexer::unescape(raw, awascii)?.as_slice() {
+            [byte] => *byte,
+            _ => return Err("!chr expects a single character".to_string()),
+        };
 
-        MacroResult::VecAndBool(res, awascii)
+        Ok(MacroResult::VecAndBool(vec![Awatism::Blo(byte)], awascii))
     }
 
-    fn process_str(string_content: &str) -> MacroResult {
-        let mut res = Vec::new();
-        let replaced_string = string_content.replace("\\n", "\n");
-        let mut string_content = replaced_string.trim();
-
-        let mut awascii = false;
-        if string_content.chars().nth(0).unwrap() == 'a' {
-            string_content = &string_content[1..];
-            awascii = true;
-        }
-
-        if string_content.chars().nth(0).unwrap() != '"' {
-            panic!("Missing opening '\"'");
-        }
-        if string_content.len() == 1 || string_content.chars().nth_back(0).unwrap() != '"' {
-            panic!("Missing closing '\"'");
-        }
-        let string_content = &string_content[1..string_content.len() - 1];
+    fn process_str(token: &Token) -> Result<MacroResult, String> {
+        let (awascii, raw) = match &token.kind {
+            TokenKind::Str { awascii, raw } => (*awascii, raw),
+            _ => return Err(format!("Expected a string literal, found '{}'", token)),
+        };
 
-        let mut temp_res = Vec::new();
-        for c in string_content.chars() {
-            if awascii {
-                temp_res.push(Awatism::Blo(AWA_SCII.find(c).unwrap() as u8));
-            } else {
-                temp_res.push(Awatism::Blo(c as u8));
-            }
-        }
+        let mut res = Vec::new();
+        let mut temp_res: Vec<Awatism> = lexer::unescape(raw, awascii)?
+            .into_iter()
+            .map(Awatism::Blo)
+            .collect();
 
         let mut i = 0;
         let chunk_size = 31; // max allowed value of u5 for srn arg
         while !temp_res.is_empty() {
             let len = temp_res.len();
-            let end = if len > chunk_size {
-                len - chunk_size
-            } else {
-                0
-            };
+            let end = len.saturating_sub(chunk_size);
 
             for _ in end..len {
                 res.push(temp_res.pop().unwrap());
@@ -501,91 +1466,268 @@ pub mod awasm {
             i += 1;
         }
 
-        MacroResult::VecAndBool(res, awascii)
+        Ok(MacroResult::VecAndBool(res, awascii))
     }
 }
 
 pub mod awatalk {
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::io::{self, BufReader, Read};
+
     use crate::{Awatism, Instruction};
 
-    pub fn parse_string(content: &str) -> Vec<Instruction> {
-        let mut instructions = Vec::new();
+    /// Invalid awatalk, at a byte offset of the input and a bit of the decoded stream
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct DecodeError {
+        pub message: String,
+        pub offset: usize,
+        pub bit: usize,
+    }
 
-        let normalized = content
-            .to_lowercase()
-            .chars()
-            .filter(|&c| c == 'a' || c == 'w' || c == ' ')
-            .collect::<String>()
-            .split_whitespace()
-            .collect::<Vec<&str>>()
-            .join(" ");
-
-        if !normalized.starts_with("awa") {
-            panic!("Not valid awatalk");
-        }
-        let mut remaining = &normalized[3..];
-
-        let mapping = [(" awa", '0'), ("wa", '1')];
-
-        let mut binary_str = String::new();
-        let mut found_op = false;
-        let mut num_bits = 0;
-        let mut op_index = 0;
-        let mut arg_index = 0;
-        let mut current_index = 0;
-        let mut arg_opcode = 0x00;
-        while !remaining.is_empty() {
-            let mut matched = false;
-            let mut opcode = 0x00;
-            for &(pattern, bit) in &mapping {
-                if remaining.starts_with(pattern) {
-                    binary_str.push(bit);
-                    remaining = &remaining[pattern.len()..];
-                    matched = true;
-                    current_index += 1;
-
-                    // finish reading args, add op code instruction
-                    if found_op && current_index - arg_index == num_bits {
-                        let op_str = &binary_str[arg_index..current_index];
-                        let arg = u8::from_str_radix(op_str, 2).unwrap();
-                        instructions.push(Instruction {
-                            awatism: Awatism::from_u8(arg_opcode, arg).unwrap(),
-                        });
-                        op_index = current_index;
-                        found_op = false;
-                    }
+    impl fmt::Display for DecodeError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "byte {}, bit {}: {}",
+                self.offset, self.bit, self.message
+            )
+        }
+    }
 
-                    // found op code
-                    if !found_op && current_index - op_index == 5 {
-                        let op_str = &binary_str[op_index..current_index];
-                        opcode = u8::from_str_radix(op_str, 2).unwrap();
-                        op_index = current_index;
-                        found_op = true;
-                    }
+    /// Decoder fed one character at a time
+    ///
+    /// Words are separated by spaces and other characters than `a` and `w` are ignored, as
+    /// are `;` comments up to the end of the line and the spaces before them. Every word
+    /// must be ` awa` for a 0 bit followed by `wa` for each 1 bit, the first `awa` being
+    /// the header
+    #[derive(Default)]
+    struct Decoder {
+        /// Stop at the first invalid word, drop incomplete instructions and skip unknown
+        /// opcodes instead of failing
+        lenient: bool,
+        stopped: bool,
+        comment: bool,
+        /// A space was read since the last `a` or `w`
+        space: bool,
+        header: bool,
+        /// Characters of the current word with their byte offsets
+        word: Vec<(usize, char)>,
+        /// Number of bits decoded so far
+        bit: usize,
+        value: u8,
+        pending: usize,
+        op: Option<u8>,
+        /// Byte offset and bit of the instruction being decoded
+        start: (usize, usize),
+        decoded: VecDeque<Instruction>,
+    }
 
-                    // set start of arg check
-                    if found_op && Awatism::needs_args(opcode) {
-                        arg_index = current_index;
-                        arg_opcode = opcode;
-                        num_bits = Awatism::arg_bits(opcode);
+    impl Decoder {
+        fn new(lenient: bool) -> Self {
+            Decoder {
+                lenient,
+                ..Default::default()
+            }
+        }
+
+        fn push(&mut self, offset: usize, c: char) -> Result<(), DecodeError> {
+            match c.to_ascii_lowercase() {
+                '\n' => self.comment = false,
+                _ if self.comment => {}
+                ';' => {
+                    self.comment = true;
+                    self.space = false;
+                }
+                c @ ('a' | 'w') => {
+                    if std::mem::take(&mut self.space) {
+                        self.end_word()?;
                     }
+                    self.word.push((offset, c));
+                }
+                ' ' => self.space = true,
+                _ => {}
+            }
+            Ok(())
+        }
 
-                    // does op code not needs args and continue checking, add instruction
-                    if found_op && op_index == current_index && !Awatism::needs_args(opcode) {
-                        instructions.push(Instruction {
-                            awatism: Awatism::from_u8(opcode, 0x00).unwrap(),
-                        });
-                        found_op = false;
+        fn finish(&mut self) -> Result<(), DecodeError> {
+            self.end_word()?;
+            if !self.header {
+                return Err(self.error(0, "Awatalk must start with 'awa'".to_string()));
+            }
+            if self.lenient || self.stopped || self.pending == 0 && self.op.is_none() {
+                return Ok(());
+            }
+            let message = match self.op {
+                Some(op) => format!(
+                    "Truncated argument of opcode 0x{:02X}, {} of {} bits",
+                    op,
+                    self.pending,
+                    Awatism::arg_bits(op)
+                ),
+                None => format!("Truncated opcode, {} of 5 bits", self.pending),
+            };
+            Err(DecodeError {
+                message,
+                offset: self.start.0,
+                bit: self.start.1,
+            })
+        }
+
+        fn error(&self, offset: usize, message: String) -> DecodeError {
+            DecodeError {
+                message,
+                offset,
+                bit: self.bit,
+            }
+        }
+
+        fn end_word(&mut self) -> Result<(), DecodeError> {
+            if self.word.is_empty() || self.stopped {
+                self.word.clear();
+                return Ok(());
+            }
+            let word = std::mem::take(&mut self.word);
+            let text: String = word.iter().map(|&(_, c)| c).collect();
+            let valid = text.starts_with("awa")
+                && text.len() % 2 == 1
+                && text.as_bytes()[3..].chunks(2).all(|pair| pair == b"wa");
+
+            if !self.header {
+                if !valid {
+                    return Err(self.error(word[0].0, "Awatalk must start with 'awa'".to_string()));
+                }
+                self.header = true;
+            } else if !valid {
+                if self.lenient {
+                    self.stopped = true;
+                    return Ok(());
+                }
+                return Err(self.error(word[0].0, format!("Invalid awatalk token '{}'", text)));
+            } else {
+                self.push_bit(0, word[0].0)?;
+            }
+            for &(offset, _) in word[3..].iter().step_by(2) {
+                self.push_bit(1, offset)?;
+            }
+            Ok(())
+        }
+
+        fn push_bit(&mut self, bit: u8, offset: usize) -> Result<(), DecodeError> {
+            if self.pending == 0 && self.op.is_none() {
+                self.start = (offset, self.bit);
+            }
+            self.value = self.value << 1 | bit;
+            self.pending += 1;
+            self.bit += 1;
+
+            match self.op {
+                None if self.pending == 5 => {
+                    let op = self.value;
+                    self.value = 0;
+                    self.pending = 0;
+                    if Awatism::needs_args(op) {
+                        self.op = Some(op);
+                    } else {
+                        self.emit(op, 0)?;
                     }
+                }
+                Some(op) if self.pending == Awatism::arg_bits(op) => {
+                    let arg = self.value;
+                    self.value = 0;
+                    self.pending = 0;
+                    self.op = None;
+                    self.emit(op, arg)?;
+                }
+                _ => {}
+            }
+            Ok(())
+        }
 
-                    break;
+        fn emit(&mut self, op: u8, arg: u8) -> Result<(), DecodeError> {
+            match Awatism::from_u8(op, arg) {
+                Some(awatism) => self.decoded.push_back(Instruction { awatism }),
+                None if self.lenient => {}
+                None => {
+                    return Err(DecodeError {
+                        message: format!("Unknown opcode 0x{:02X}", op),
+                        offset: self.start.0,
+                        bit: self.start.1,
+                    })
                 }
             }
-            if !matched {
-                break; // invalid awa code
+            Ok(())
+        }
+    }
+
+    /// Instructions decoded from awatalk as it is read, ends after the first error
+    pub struct Instructions<R: Read> {
+        bytes: io::Bytes<BufReader<R>>,
+        decoder: Decoder,
+        offset: usize,
+        error: Option<DecodeError>,
+        done: bool,
+    }
+
+    impl<R: Read> Instructions<R> {
+        pub fn new(reader: R, lenient: bool) -> Self {
+            Instructions {
+                bytes: BufReader::new(reader).bytes(),
+                decoder: Decoder::new(lenient),
+                offset: 0,
+                error: None,
+                done: false,
+            }
+        }
+    }
+
+    impl<R: Read> Iterator for Instructions<R> {
+        type Item = Result<Instruction, DecodeError>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            loop {
+                // instructions decoded before an error come first
+                if let Some(instruction) = self.decoder.decoded.pop_front() {
+                    return Some(Ok(instruction));
+                }
+                if self.done {
+                    return self.error.take().map(Err);
+                }
+                let result = match self.bytes.next() {
+                    Some(Ok(byte)) => {
+                        self.offset += 1;
+                        self.decoder.push(self.offset - 1, byte as char)
+                    }
+                    Some(Err(err)) => {
+                        self.done = true;
+                        Err(DecodeError {
+                            message: format!("Could not read input: {}", err),
+                            offset: self.offset,
+                            bit: self.decoder.bit,
+                        })
+                    }
+                    None => {
+                        self.done = true;
+                        self.decoder.finish()
+                    }
+                };
+                if let Err(err) = result {
+                    self.error = Some(err);
+                    self.done = true;
+                }
             }
         }
+    }
 
-        instructions
+    /// Decode awatalk, failing on invalid tokens, unknown opcodes and truncated
+    /// instructions unless `lenient`
+    pub fn decode(content: &str, lenient: bool) -> Result<Vec<Instruction>, DecodeError> {
+        Instructions::new(content.as_bytes(), lenient).collect()
+    }
+
+    /// Decode awatalk leniently, stopping at the first invalid token
+    pub fn parse_string(content: &str) -> Vec<Instruction> {
+        decode(content, true).unwrap_or_else(|err| panic!("Not valid awatalk: {}", err))
     }
 }
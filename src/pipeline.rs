@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::assembler::{self, AwaFormat};
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::linker::{self, ObjectFile};
+use crate::{parser, Awatism, Instruction};
+
+/// Formats a program can be read from and written to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Awasm,
+    Awa,
+    Object,
+}
+
+impl Format {
+    /// Format named by `--from` and `--to`, the same as its file extension
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "awasm" => Ok(Format::Awasm),
+            "awa" => Ok(Format::Awa),
+            "o" => Ok(Format::Object),
+            _ => Err(format!(
+                "Unknown format '{}', expected awasm, awa or o",
+                name
+            )),
+        }
+    }
+
+    /// Format of a path with a known extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        Format::from_name(extension).ok()
+    }
+
+    /// Format of a file from its extension, or else its content, `None` when the file
+    /// cannot be read
+    pub fn of_file(path: &str) -> Option<Self> {
+        if let Some(format) = Format::from_path(path) {
+            return Some(format);
+        }
+        let mut sample = Vec::new();
+        File::open(path)
+            .and_then(|file| file.take(DETECT_SAMPLE as u64).read_to_end(&mut sample))
+            .ok()?;
+        Some(Format::detect(&sample).format)
+    }
+
+    /// Guess the format of the start of a program
+    ///
+    /// Relocatable objects are known by their magic bytes and flat objects by control
+    /// bytes text does not have. Text is awatalk by the share of `awa(wa)*` words and awasm
+    /// by the share of lines starting with a mnemonic, directive or label
+    pub fn detect(sample: &[u8]) -> Detection {
+        if ObjectFile::is_object(sample) {
+            return Detection {
+                format: Format::Object,
+                confidence: 1.0,
+            };
+        }
+
+        // a sample may end inside a character
+        let text = match std::str::from_utf8(sample) {
+            Ok(text) => Some(text),
+            Err(err) if err.error_len().is_none() => {
+                Some(std::str::from_utf8(&sample[..err.valid_up_to()]).unwrap())
+            }
+            Err(_) => None,
+        };
+        let text = match text {
+            Some(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => text,
+            _ => {
+                let pairs = sample.chunks_exact(2);
+                let count = pairs.len().max(1);
+                let valid = pairs
+                    .filter(|pair| Awatism::from_u8(pair[0], pair[1]).is_some())
+                    .count();
+                return Detection {
+                    format: Format::Object,
+                    confidence: valid as f32 / count as f32,
+                };
+            }
+        };
+
+        let (mut words, mut awa_words, mut lines, mut awasm_lines) = (0, 0, 0, 0);
+        for line in text.lines() {
+            let code = line.split(';').next().unwrap_or_default();
+            let line_words: Vec<&str> = code.split_whitespace().collect();
+            let Some(first) = line_words.first() else {
+                continue;
+            };
+            words += line_words.len();
+            awa_words += line_words.iter().filter(|word| is_awa_word(word)).count();
+            lines += 1;
+            if parser::awasm::MNEMONICS.contains(first)
+                || first.starts_with('!')
+                || first.ends_with(':')
+            {
+                awasm_lines += 1;
+            }
+        }
+
+        let awa = awa_words as f32 / words.max(1) as f32;
+        let awasm = awasm_lines as f32 / lines.max(1) as f32;
+        if awa > awasm {
+            Detection {
+                format: Format::Awa,
+                confidence: awa,
+            }
+        } else {
+            Detection {
+                format: Format::Awasm,
+                confidence: awasm,
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Awasm => "awasm",
+            Format::Awa => "awa",
+            Format::Object => "o",
+        }
+    }
+}
+
+/// Bytes from the start of a program looked at to detect its format
+pub const DETECT_SAMPLE: usize = 4096;
+
+/// Format guessed from the content of a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub format: Format,
+    /// From 0 without any evidence to 1 when certain
+    pub confidence: f32,
+}
+
+/// Detect the format of a reader such as stdin, returning a reader that still starts
+/// at the beginning of the program
+pub fn detect_reader<R: Read>(mut reader: R) -> Result<(Detection, impl Read), String> {
+    let mut sample = Vec::new();
+    reader
+        .by_ref()
+        .take(DETECT_SAMPLE as u64)
+        .read_to_end(&mut sample)
+        .map_err(|err| format!("Could not read input: {}", err))?;
+    Ok((
+        Format::detect(&sample),
+        io::Cursor::new(sample).chain(reader),
+    ))
+}
+
+/// `awa` followed by any number of `wa`
+fn is_awa_word(word: &str) -> bool {
+    word.strip_prefix("awa").is_some_and(|bits| {
+        bits.len() % 2 == 0 && bits.as_bytes().chunks(2).all(|bit| bit == b"wa")
+    })
+}
+
+/// Settings for reading and writing programs, shared by the CLI and embedders
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Include paths separated by ';' for awasm sources
+    pub include_paths: String,
+    /// Directories every included file must be inside, as in `Policy::include_dirs`
+    pub include_dirs: Option<Vec<PathBuf>>,
+    /// Decode awatalk up to the first invalid token
+    pub lenient: bool,
+    /// Constants assigned before parsing awasm, as if by `!set`
+    pub defines: Vec<(String, i64)>,
+    /// Layout of awatalk output
+    pub awa_format: AwaFormat,
+}
+
+/// Read a program to a flat object that can be run
+///
+/// `name` is the path awasm includes are relative to and prefixes errors, relocatable
+/// objects are linked on their own. The whole program is read before it can run, see
+/// `run_program` for running awatalk as it is read
+pub fn read_program(
+    mut reader: impl Read,
+    format: Format,
+    name: &str,
+    options: &Options,
+) -> Result<Vec<u8>, String> {
+    let prefix = |err: String| prefixed(name, err);
+
+    match format {
+        Format::Awasm => {
+            let mut source = String::new();
+            reader
+                .read_to_string(&mut source)
+                .map_err(|err| prefix(format!("Could not read input: {}", err)))?;
+            assemble_awasm(&source, name, options)
+        }
+        Format::Awa => {
+            let instructions = parser::awatalk::Instructions::new(reader, options.lenient)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| prefix(err.to_string()))?;
+            Ok(assembler::make_object_vec(&instructions))
+        }
+        Format::Object => {
+            let mut bytes = Vec::new();
+            reader
+                .read_to_end(&mut bytes)
+                .map_err(|err| prefix(format!("Could not read input: {}", err)))?;
+            if !ObjectFile::is_object(&bytes) {
+                return Ok(bytes);
+            }
+            // a relocatable object runs when it links on its own
+            let object = ObjectFile::from_bytes(&bytes).map_err(prefix)?;
+            linker::link(&[(name.to_string(), object)])
+        }
+    }
+}
+
+/// Read a program and run it, awatalk runs while it is decoded so a program piped in
+/// starts before its input ends, and an invalid word stops it once execution reaches it
+pub fn run_program(
+    reader: impl Read,
+    format: Format,
+    name: &str,
+    options: &Options,
+    interpreter: &mut Interpreter,
+) -> Result<(), String> {
+    let result = match format {
+        Format::Awa => interpreter
+            .run_instructions(parser::awatalk::Instructions::new(reader, options.lenient)),
+        _ => interpreter.run(read_program(reader, format, name, options)?),
+    };
+    result.map_err(|err| match err {
+        // reported like the decode errors of read_program
+        RuntimeError::InvalidProgram(err) => prefixed(name, err),
+        err => format!("Runtime error: {}", err),
+    })
+}
+
+fn prefixed(name: &str, err: String) -> String {
+    if name.is_empty() {
+        err
+    } else {
+        format!("{}: {}", name, err)
+    }
+}
+
+/// Assemble awasm source to a flat object, `path` is where includes are resolved from
+pub fn assemble_awasm(source: &str, path: &str, options: &Options) -> Result<Vec<u8>, String> {
+    assemble(source, path, options, &mut HashSet::new())
+}
+
+/// Assemble an awasm file, also returning the files it included, which are known up to
+/// the failing include when assembling fails
+pub fn assemble_awasm_file(
+    path: &str,
+    options: &Options,
+) -> (Result<Vec<u8>, String>, Vec<String>) {
+    let mut included = HashSet::new();
+    let result = fs::read_to_string(path)
+        .map_err(|err| format!("Could not read {}: {}", path, err))
+        .and_then(|source| assemble(&source, path, options, &mut included));
+    (result, included.into_iter().collect())
+}
+
+fn assemble(
+    source: &str,
+    path: &str,
+    options: &Options,
+    already_included: &mut HashSet<String>,
+) -> Result<Vec<u8>, String> {
+    let mut macro_table = parser::awasm::MacroTable::new();
+    let instructions = parse(&mut macro_table, source, path, options, already_included)?;
+    Ok(assembler::make_object_vec(&instructions))
+}
+
+/// Assemble an awasm file to a relocatable object, leaving undefined `jro` and `call`
+/// targets to the linker, with a listing of the instructions when `listing` is set
+pub fn assemble_relocatable(
+    path: &str,
+    options: &Options,
+    listing: bool,
+) -> Result<(ObjectFile, Option<String>), String> {
+    let source =
+        fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+    let mut macro_table = parser::awasm::MacroTable::new();
+    macro_table.set_relocatable(true);
+    if listing {
+        macro_table.record_origins();
+    }
+    let instructions = parse(
+        &mut macro_table,
+        &source,
+        path,
+        options,
+        &mut HashSet::new(),
+    )?;
+
+    let listing = listing.then(|| assembler::make_listing(&instructions, macro_table.origins()));
+    let object = ObjectFile::assemble(&instructions, |label| macro_table.is_global_label(label));
+    Ok((object, listing))
+}
+
+/// Parse awasm source with the defines and include settings of `options`
+fn parse(
+    macro_table: &mut parser::awasm::MacroTable,
+    source: &str,
+    path: &str,
+    options: &Options,
+    already_included: &mut HashSet<String>,
+) -> Result<Vec<Instruction>, String> {
+    for (name, value) in &options.defines {
+        macro_table.define(name, *value);
+    }
+    if let Some(include_dirs) = &options.include_dirs {
+        macro_table.restrict_includes(include_dirs);
+    }
+    parser::awasm::parse_lines(
+        macro_table,
+        already_included,
+        &mut HashSet::new(),
+        &options.include_paths,
+        path,
+        source.lines().map(|line| line.to_string()),
+    )
+    .map_err(|err| err.to_string())
+}
+
+/// Write a flat object in another format
+pub fn write_program(object: &[u8], format: Format, options: &Options) -> Vec<u8> {
+    match format {
+        Format::Awasm => assembler::object_to_awasm(&object.to_vec()).into_bytes(),
+        Format::Awa => assembler::object_to_awa_formatted(object, &options.awa_format).into_bytes(),
+        Format::Object => object.to_vec(),
+    }
+}
+
+/// Convert a program between formats, a program already in the wanted format is checked
+/// and copied unchanged
+pub fn convert(
+    mut reader: impl Read,
+    from: Format,
+    to: Format,
+    name: &str,
+    options: &Options,
+) -> Result<Vec<u8>, String> {
+    if from == to {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|err| format!("Could not read input: {}", err))?;
+        if from != Format::Object {
+            read_program(bytes.as_slice(), from, name, options)?;
+        }
+        return Ok(bytes);
+    }
+    let object = read_program(reader, from, name, options)?;
+    Ok(write_program(&object, to, options))
+}
@@ -0,0 +1,224 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Resource limits enforced while interpreting, nothing is limited by default
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Maximum number of executed instructions
+    pub max_instructions: Option<usize>,
+    /// Maximum number of bubbles in the abyss
+    pub max_abyss: Option<usize>,
+    /// Maximum number of bubbles inside a double bubble
+    pub max_bubble_size: Option<usize>,
+    /// Maximum nesting depth of double bubbles
+    pub max_depth: Option<usize>,
+    /// Maximum number of bubbles in the abyss, counting every nested bubble
+    pub max_bubbles: Option<usize>,
+    /// Maximum wall-clock time spent interpreting, checked between instructions so an
+    /// instruction that blocks, like `red` waiting for input or a long `lib` call, is not
+    /// interrupted
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    /// Parse limits separated by ',', e.g. `instructions=100000,depth=8,timeout=5000`
+    ///
+    /// Keys are `instructions`, `abyss`, `bubble_size`, `depth`, `bubbles` and `timeout`
+    /// in milliseconds
+    pub fn parse(spec: &str) -> Result<Limits, String> {
+        let mut limits = Limits::default();
+        limits.apply(spec)?;
+        Ok(limits)
+    }
+
+    /// Apply limits in the format of `Limits::parse` on top of the current ones
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        for setting in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = match setting.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("Expected 'limit=value', found '{}'", setting)),
+            };
+            self.set(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Set a single limit by name
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "instructions" => self.max_instructions = Some(parse_value(key, value)?),
+            "abyss" => self.max_abyss = Some(parse_value(key, value)?),
+            "bubble_size" => self.max_bubble_size = Some(parse_value(key, value)?),
+            "depth" => self.max_depth = Some(parse_value(key, value)?),
+            "bubbles" => self.max_bubbles = Some(parse_value(key, value)?),
+            "timeout" => self.timeout = Some(Duration::from_millis(parse_value(key, value)?)),
+            _ => return Err(format!("Unknown limit '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Capabilities granted to a program, everything is allowed by default
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// Reject every `lib` call
+    pub disable_lib: bool,
+    /// Hide environment variables from the `sys` env service
+    pub disable_env: bool,
+    /// Shared libraries that may be loaded, by file stem with or without the `lib` prefix,
+    /// `std` covers the built-in functions
+    pub allow_libs: Option<Vec<String>>,
+    /// Function names that may be called with `lib`
+    pub allow_fns: Option<Vec<String>>,
+    /// Directories that `!include` may search and that every included file must be inside
+    pub include_dirs: Option<Vec<PathBuf>>,
+    /// Resource limits enforced by the interpreter
+    pub limits: Limits,
+}
+
+impl Policy {
+    /// Read a policy file of `key = value` lines, `#` starts a comment at the start of a
+    /// line or after whitespace, so values like `./lib#2` keep their `#`
+    ///
+    /// ```text
+    /// disable_lib = false
+    /// disable_env = true
+    /// allow_lib = std, foo
+    /// allow_fn = std.sqrt
+    /// include_dir = ./lib
+    /// max_instructions = 100000
+    /// max_abyss = 1024
+    /// max_bubble_size = 4096
+    /// max_depth = 16
+    /// max_bubbles = 65536
+    /// timeout_ms = 5000
+    /// ```
+    pub fn from_file(filename: &str) -> Result<Policy, String> {
+        let content = fs::read_to_string(filename)
+            .map_err(|err| format!("Could not read policy file {}: {}", filename, err))?;
+        let mut policy = Policy::default();
+        for (number, line) in content.lines().enumerate() {
+            policy
+                .apply(line)
+                .map_err(|err| format!("{}:{}: {}", filename, number + 1, err))?;
+        }
+        Ok(policy)
+    }
+
+    /// Apply a single `key = value` setting, list values are separated by ',' or ';'
+    pub fn apply(&mut self, line: &str) -> Result<(), String> {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err(format!("Expected 'key = value', found '{}'", line)),
+        };
+        let list = || {
+            value
+                .split([',', ';'])
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+        };
+
+        match key {
+            "disable_lib" => self.disable_lib = parse_value(key, value)?,
+            "disable_env" => self.disable_env = parse_value(key, value)?,
+            "allow_lib" => self.allow_libs.get_or_insert_with(Vec::new).extend(list()),
+            "allow_fn" => self.allow_fns.get_or_insert_with(Vec::new).extend(list()),
+            "include_dir" => self
+                .include_dirs
+                .get_or_insert_with(Vec::new)
+                .extend(list().map(PathBuf::from)),
+            "max_instructions" => self.limits.set("instructions", value)?,
+            "max_abyss" => self.limits.set("abyss", value)?,
+            "max_bubble_size" => self.limits.set("bubble_size", value)?,
+            "max_depth" => self.limits.set("depth", value)?,
+            "max_bubbles" => self.limits.set("bubbles", value)?,
+            "timeout_ms" => self.limits.set("timeout", value)?,
+            _ => return Err(format!("Unknown policy setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    pub fn allows_lib(&self, lib_path: &str) -> bool {
+        let allow_libs = match &self.allow_libs {
+            Some(allow_libs) => allow_libs,
+            None => return true,
+        };
+
+        let stem = Path::new(lib_path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+        let name = stem.strip_prefix("lib").unwrap_or(stem);
+        allow_libs.iter().any(|lib| lib == stem || lib == name)
+    }
+
+    pub fn allows_fn(&self, fn_name: &str) -> bool {
+        match &self.allow_fns {
+            Some(allow_fns) => allow_fns.iter().any(|f| f == fn_name),
+            None => true,
+        }
+    }
+
+    /// Check that every include path separated by ';' is inside an allowed directory,
+    /// without include paths the allowed directories themselves are searched
+    pub fn filter_include_paths(&self, include_paths: &str) -> Result<String, String> {
+        let include_dirs = match &self.include_dirs {
+            Some(include_dirs) => include_dirs,
+            None => return Ok(include_paths.to_string()),
+        };
+
+        let paths: Vec<&str> = include_paths
+            .split(';')
+            .filter(|path| !path.is_empty())
+            .collect();
+        if paths.is_empty() {
+            let dirs: Vec<&str> = include_dirs.iter().filter_map(|dir| dir.to_str()).collect();
+            return Ok(dirs.join(";"));
+        }
+
+        let allowed: Vec<PathBuf> = include_dirs
+            .iter()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .collect();
+        for path in &paths {
+            let inside = match Path::new(path).canonicalize() {
+                Ok(path) => allowed.iter().any(|dir| path.starts_with(dir)),
+                Err(_) => false,
+            };
+            if !inside {
+                return Err(format!(
+                    "Include path '{}' is outside the allowed include directories",
+                    path
+                ));
+            }
+        }
+        Ok(paths.join(";"))
+    }
+}
+
+/// Cut a line at the first `#` that starts it or follows whitespace
+fn strip_comment(line: &str) -> &str {
+    let mut previous = None;
+    for (index, c) in line.char_indices() {
+        if c == '#' && previous.is_none_or(char::is_whitespace) {
+            return &line[..index];
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for '{}'", value, key))
+}
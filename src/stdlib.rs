@@ -0,0 +1,273 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{interpreter::Bubble, Interpreter, LibArg, AWA_SCII};
+
+type UnaryOp = fn(f32) -> f32;
+type BinaryOp = fn(f32, f32) -> f32;
+
+/// Options for the built-in `std` lib functions
+#[derive(Debug, Clone)]
+pub struct StdConfig {
+    /// Allow `std.read_file`, `std.write_file` and `std.append_file`
+    pub allow_files: bool,
+    /// Resolve file paths relative to this directory, rejecting paths that escape it
+    pub file_root: Option<PathBuf>,
+}
+
+impl Default for StdConfig {
+    fn default() -> Self {
+        StdConfig {
+            allow_files: true,
+            file_root: None,
+        }
+    }
+}
+
+/// Register the built-in `std` lib functions
+///
+/// Numbers are passed and returned as 4 byte double bubbles like `!_i32` and `!_f32`,
+/// strings are returned in the same layout as `!str`. The file functions read `config`
+/// on every call, so it can change after registering
+pub fn register(interpreter: &mut Interpreter, config: &Rc<RefCell<StdConfig>>) {
+    register_math(interpreter);
+    register_strings(interpreter);
+    register_random(interpreter);
+    register_time(interpreter);
+    register_files(interpreter, config);
+}
+
+fn register_math(interpreter: &mut Interpreter) {
+    let unary: [(&str, UnaryOp); 8] = [
+        ("std.sqrt", f32::sqrt),
+        ("std.sin", f32::sin),
+        ("std.cos", f32::cos),
+        ("std.tan", f32::tan),
+        ("std.floor", f32::floor),
+        ("std.ceil", f32::ceil),
+        ("std.round", f32::round),
+        ("std.fabs", f32::abs),
+    ];
+    for (name, op) in unary {
+        interpreter.register_fn(name, move |args: &[LibArg]| {
+            Ok(vec![f32_bubble(op(f32_arg(args, 0)?))])
+        });
+    }
+
+    let binary: [(&str, BinaryOp); 5] = [
+        ("std.fadd", |a, b| a + b),
+        ("std.fsub", |a, b| a - b),
+        ("std.fmul", |a, b| a * b),
+        ("std.fdiv", |a, b| a / b),
+        ("std.pow", f32::powf),
+    ];
+    for (name, op) in binary {
+        interpreter.register_fn(name, move |args: &[LibArg]| {
+            Ok(vec![f32_bubble(op(f32_arg(args, 0)?, f32_arg(args, 1)?))])
+        });
+    }
+
+    interpreter.register_fn("std.itof", |args: &[LibArg]| {
+        Ok(vec![f32_bubble(i32_arg(args, 0)? as f32)])
+    });
+    interpreter.register_fn("std.ftoi", |args: &[LibArg]| {
+        Ok(vec![i32_bubble(f32_arg(args, 0)? as i32)])
+    });
+}
+
+fn register_strings(interpreter: &mut Interpreter) {
+    interpreter.register_fn("std.itoa", |args: &[LibArg]| {
+        Ok(vec![ascii_bubble(&i32_arg(args, 0)?.to_string())])
+    });
+    interpreter.register_fn("std.itoa_awa", |args: &[LibArg]| {
+        // AWA-SCII has digits but no '-'
+        let val = i32_arg(args, 0)?;
+        if val < 0 {
+            return Err(format!("{} is negative, AWA-SCII has no '-'", val));
+        }
+        Ok(vec![awascii_bubble(&val.to_string())?])
+    });
+    interpreter.register_fn("std.atoi", |args: &[LibArg]| {
+        let string = str_arg(args, 0)?;
+        match string.trim().parse::<i32>() {
+            Ok(val) => Ok(vec![i32_bubble(val)]),
+            Err(_) => Err(format!("'{}' is not an integer", string)),
+        }
+    });
+}
+
+fn register_random(interpreter: &mut Interpreter) {
+    // xorshift32, seeded from the clock unless `std.srand` is called
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |time| time.subsec_nanos() | 1);
+    let state = Rc::new(Cell::new(seed));
+
+    let srand_state = state.clone();
+    interpreter.register_fn("std.srand", move |args: &[LibArg]| {
+        // xorshift gets stuck on a zero state
+        srand_state.set((i32_arg(args, 0)? as u32).max(1));
+        Ok(vec![])
+    });
+
+    interpreter.register_fn("std.rand", move |args: &[LibArg]| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        state.set(x);
+
+        // optional exclusive upper bound
+        let val = match args.first() {
+            Some(_) => {
+                let max = i32_arg(args, 0)?;
+                if max <= 0 {
+                    return Err("std.rand bound must be positive".to_string());
+                }
+                (x % max as u32) as i32
+            }
+            None => (x >> 1) as i32,
+        };
+        Ok(vec![i32_bubble(val)])
+    });
+}
+
+fn register_time(interpreter: &mut Interpreter) {
+    let start = Instant::now();
+    interpreter.register_fn("std.time_ms", move |_: &[LibArg]| {
+        Ok(vec![i32_bubble(start.elapsed().as_millis() as i32)])
+    });
+    interpreter.register_fn("std.sleep_ms", |args: &[LibArg]| {
+        thread::sleep(Duration::from_millis(i32_arg(args, 0)?.max(0) as u64));
+        Ok(vec![])
+    });
+}
+
+fn register_files(interpreter: &mut Interpreter, config: &Rc<RefCell<StdConfig>>) {
+    let read_config = config.clone();
+    interpreter.register_fn("std.read_file", move |args: &[LibArg]| {
+        let path = resolve_path(&read_config.borrow(), &str_arg(args, 0)?)?;
+        match fs::read(&path) {
+            Ok(content) => Ok(vec![Bubble::Double(
+                content
+                    .iter()
+                    .rev()
+                    .map(|b| Bubble::Simple(*b as i32))
+                    .collect(),
+            )]),
+            Err(err) => Err(format!("Could not read {}: {}", path.display(), err)),
+        }
+    });
+
+    for (name, append) in [("std.write_file", false), ("std.append_file", true)] {
+        let write_config = config.clone();
+        interpreter.register_fn(name, move |args: &[LibArg]| {
+            let path = resolve_path(&write_config.borrow(), &str_arg(args, 0)?)?;
+            let content = match args.get(1) {
+                Some(LibArg::Str(bytes)) => bytes.clone(),
+                Some(LibArg::AwaStr(string)) => string.as_bytes().to_vec(),
+                Some(arg) => arg.to_bytes(),
+                None => return Err("Missing file content argument".to_string()),
+            };
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(&path);
+            match file.and_then(|mut file| file.write_all(&content)) {
+                Ok(_) => Ok(vec![]),
+                Err(err) => Err(format!("Could not write {}: {}", path.display(), err)),
+            }
+        });
+    }
+}
+
+fn resolve_path(config: &StdConfig, path: &str) -> Result<PathBuf, String> {
+    if !config.allow_files {
+        return Err("File access is disabled".to_string());
+    }
+
+    let root = match &config.file_root {
+        Some(root) => root,
+        None => return Ok(PathBuf::from(path)),
+    };
+
+    // only plain relative components may be joined onto the root
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!("Path '{}' is outside of {}", path, root.display()));
+    }
+    Ok(root.join(relative))
+}
+
+fn i32_arg(args: &[LibArg], index: usize) -> Result<i32, String> {
+    args.get(index)
+        .and_then(LibArg::as_i32)
+        .ok_or(format!("Expected i32 argument {}", index))
+}
+
+fn f32_arg(args: &[LibArg], index: usize) -> Result<f32, String> {
+    args.get(index)
+        .and_then(LibArg::as_f32)
+        .ok_or(format!("Expected f32 argument {}", index))
+}
+
+fn str_arg(args: &[LibArg], index: usize) -> Result<String, String> {
+    args.get(index)
+        .and_then(LibArg::as_str)
+        .ok_or(format!("Expected string argument {}", index))
+}
+
+fn bytes_bubble(bytes: &[u8]) -> Bubble {
+    // same layout as `!i32` and `!i64`, each byte pushed with blo
+    Bubble::Double(
+        bytes
+            .iter()
+            .map(|b| Bubble::Simple(*b as i8 as i32))
+            .collect(),
+    )
+}
+
+fn i32_bubble(val: i32) -> Bubble {
+    bytes_bubble(&val.to_le_bytes())
+}
+
+pub(crate) fn i64_bubble(val: i64) -> Bubble {
+    bytes_bubble(&val.to_le_bytes())
+}
+
+fn f32_bubble(val: f32) -> Bubble {
+    bytes_bubble(&val.to_le_bytes())
+}
+
+pub(crate) fn ascii_bubble(string: &str) -> Bubble {
+    Bubble::Double(
+        string
+            .bytes()
+            .rev()
+            .map(|b| Bubble::Simple(b as i32))
+            .collect(),
+    )
+}
+
+pub(crate) fn awascii_bubble(string: &str) -> Result<Bubble, String> {
+    let mut bubbles = Vec::new();
+    for c in string.chars().rev() {
+        match AWA_SCII.find(c) {
+            Some(index) => bubbles.push(Bubble::Simple(index as i32)),
+            None => return Err(format!("'{}' is not representable in AWA-SCII", c)),
+        }
+    }
+    Ok(Bubble::Double(bubbles))
+}
@@ -0,0 +1,51 @@
+use std::fs;
+use std::time::SystemTime;
+
+/// Modification times of the files a watched program was built from
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    files: Vec<String>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl Snapshot {
+    /// Record the modification times of `files`, `None` for files that cannot be read
+    pub fn new(files: Vec<String>) -> Snapshot {
+        let modified = modified_times(&files);
+        Snapshot { files, modified }
+    }
+
+    /// First file modified since the snapshot, or removed or created since, which means
+    /// the program has to be rebuilt and restarted
+    pub fn changed(&self) -> Option<&str> {
+        let now = modified_times(&self.files);
+        (0..self.files.len())
+            .find(|&i| now[i] != self.modified[i])
+            .map(|i| self.files[i].as_str())
+    }
+}
+
+fn modified_times(files: &[String]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| {
+            fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Arguments of the child running the watched program, the command line without
+/// `--watch`, and program arguments after `--` kept as they are
+pub fn child_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut child_args = Vec::new();
+    let mut program_args = false;
+    for arg in args {
+        program_args |= arg == "--";
+        if program_args || arg != "--watch" {
+            child_args.push(arg);
+        }
+    }
+    child_args
+}
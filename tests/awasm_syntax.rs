@@ -0,0 +1,190 @@
+mod common;
+
+use awa5_rs::ast::{self, Statement};
+use awa5_rs::lexer::{self, TokenKind};
+use awa5_rs::pipeline::{self, Options};
+
+fn kinds(source: &str) -> Vec<TokenKind> {
+    lexer::tokenize(source)
+        .unwrap()
+        .into_iter()
+        .map(|token| token.kind)
+        .collect()
+}
+
+#[test]
+fn semicolons_in_literals() {
+    assert_eq!(
+        kinds("!str \"a;b\" ; comment\nblo ';'"),
+        [
+            TokenKind::Directive("str".to_string()),
+            TokenKind::Str {
+                awascii: false,
+                raw: "a;b".to_string(),
+            },
+            TokenKind::Newline,
+            TokenKind::Ident("blo".to_string()),
+            TokenKind::Chr {
+                awascii: false,
+                raw: ";".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        pipeline::assemble_awasm("!str a\"a;b\" ; comment", "", &Options::default()),
+        pipeline::assemble_awasm("!str a\"a;b\"", "", &Options::default())
+    );
+}
+
+#[test]
+fn statements() {
+    let statements = ast::parse_program("start: blo 5 ; push\n!i32 1, 2\njro start").unwrap();
+    assert!(matches!(&statements[0], Statement::Label { name, .. } if name == "start"));
+    assert!(matches!(
+        &statements[1],
+        Statement::Instruction { mnemonic, arg: Some(arg), span }
+            if mnemonic == "blo" && arg.kind == TokenKind::Number("5".to_string()) && span.col == 8
+    ));
+    assert!(matches!(
+        &statements[2],
+        Statement::Directive { name, tokens, span } if name == "i32" && tokens.len() == 3 && span.line == 2
+    ));
+    assert!(matches!(&statements[3], Statement::Instruction { mnemonic, .. } if mnemonic == "jro"));
+}
+
+#[test]
+fn errors_with_line_and_column() {
+    let err = lexer::tokenize("blo 1\n  !str \"abc").unwrap_err();
+    assert_eq!((err.span.line, err.span.col), (2, 8));
+    assert_eq!(err.to_string(), "2:8: Missing closing '\"'");
+
+    assert_eq!(
+        common::assemble_err("blo 1\nblo 1 2"),
+        "prog.awasm:2:7: Unexpected '2' after 'blo'"
+    );
+    assert_eq!(
+        common::assemble_err("blo 1\n  , blo"),
+        "prog.awasm:2:3: Expected an instruction, found ','"
+    );
+    assert_eq!(
+        common::assemble_err("blo 1 >"),
+        "prog.awasm:1:7: Unexpected character '>'"
+    );
+}
+
+#[test]
+fn escapes() {
+    assert_eq!(
+        lexer::unescape(r#"\\\"\'\t\0\x41\u{e9}"#, false).unwrap(),
+        b"\\\"'\t\0A\xc3\xa9"
+    );
+    assert_eq!(lexer::unescape(r"a\n\x05", true).unwrap(), [2, 63, 5]);
+    assert_eq!(
+        kinds(r#"!str "\"a\"""#)[1],
+        TokenKind::Str {
+            awascii: false,
+            raw: r#"\"a\""#.to_string(),
+        }
+    );
+    assert_eq!(
+        kinds(r"blo '\''")[1],
+        TokenKind::Chr {
+            awascii: false,
+            raw: r"\'".to_string(),
+        }
+    );
+}
+
+#[test]
+fn invalid_escapes() {
+    for (raw, awascii, err) in [
+        (r"\q", false, "Unknown escape '\\q'"),
+        (
+            r"\x4",
+            false,
+            "Invalid escape '\\x4', expected two hex digits",
+        ),
+        (r"\x41", true, "'\\x41' is outside the AWA-SCII table"),
+        (
+            r"\u{e9}",
+            true,
+            "'\\u{..}' is only allowed in ascii strings",
+        ),
+        (r"\u{d800}", false, "Invalid unicode escape '\\u{d800}'"),
+        (r"\u{41", false, "Missing closing '}' in '\\u{..}'"),
+        ("a\\", false, "Trailing '\\' in literal"),
+    ] {
+        assert_eq!(lexer::unescape(raw, awascii).unwrap_err(), err, "{}", raw);
+    }
+}
+
+#[test]
+fn awascii_representable() {
+    assert_eq!(
+        lexer::unescape("a-b", true).unwrap_err(),
+        "'-' is not representable in AWA-SCII"
+    );
+    let err = common::assemble_err("blo 1\n!str a\"aw-w\"");
+    assert!(err.starts_with("prog.awasm:2:"), "{}", err);
+    assert!(
+        err.ends_with("'-' is not representable in AWA-SCII"),
+        "{}",
+        err
+    );
+    assert!(pipeline::assemble_awasm("!str \"aw-w\"", "", &Options::default()).is_ok());
+}
+
+#[test]
+fn integer_and_char_literals() {
+    assert_eq!(common::object("blo 0x7F"), [0x05, 0x7F]);
+    assert_eq!(common::object("blo -0x80"), [0x05, 0x80]);
+    assert_eq!(common::object("blo -0b1"), [0x05, 0xFF]);
+    assert_eq!(common::object("blo 'A'"), [0x05, b'A']);
+    assert_eq!(common::object("blo a'W'"), [0x05, 1]);
+    assert_eq!(common::object("blo '\\n'"), [0x05, b'\n']);
+    assert_eq!(common::object("sbm 0x1f"), [0x06, 31]);
+    assert_eq!(common::object("srn 0b101"), [0x09, 5]);
+    assert_eq!(common::object("lbl 0x0A"), [0x10, 10]);
+    assert_eq!(common::object("jmp 0b11111"), [0x11, 31]);
+}
+
+#[test]
+fn argument_range_errors() {
+    for (source, err) in [
+        (
+            "blo 128",
+            "Argument 128 does not fit in 8 bits, expected -128 to 127",
+        ),
+        (
+            "blo -129",
+            "Argument -129 does not fit in 8 bits, expected -128 to 127",
+        ),
+        (
+            "blo 0x100",
+            "Argument 0x100 does not fit in 8 bits, expected -128 to 127",
+        ),
+        (
+            "sbm 32",
+            "Argument 32 does not fit in 5 bits, expected 0 to 31",
+        ),
+        (
+            "srn -1",
+            "Argument -1 does not fit in 5 bits, expected 0 to 31",
+        ),
+        (
+            "lbl 0b100000",
+            "Argument 0b100000 does not fit in 5 bits, expected 0 to 31",
+        ),
+        (
+            "jmp 'A'",
+            "Argument 'A' does not fit in 5 bits, expected 0 to 31",
+        ),
+        ("blo 'ab'", "Expected a single character, found 'ab'"),
+        ("blo 1.5", "Invalid integer '1.5'"),
+        ("blo 0x", "Expected a number, found '0x'"),
+    ] {
+        let err_text = common::assemble_err(source);
+        assert!(err_text.starts_with("prog.awasm:1:"), "{}", err_text);
+        assert!(err_text.ends_with(err), "{}", err_text);
+    }
+}
@@ -0,0 +1,309 @@
+use std::cell::Cell;
+use std::iter;
+
+use awa5_rs::assembler::{make_object_vec, object_to_awa, object_to_awa_formatted, AwaFormat};
+use awa5_rs::interpreter::RuntimeError;
+use awa5_rs::parser::awatalk::{self, DecodeError, Instructions};
+use awa5_rs::pipeline::{self, Format, Options};
+use awa5_rs::{Awatism, Instruction, Interpreter};
+
+/// Awatalk for a string of bits, after the `awa` header
+fn awa(bits: &str) -> String {
+    let mut text = "awa".to_string();
+    for bit in bits.chars().filter(|c| *c != ' ') {
+        text += if bit == '1' { "wa" } else { " awa" };
+    }
+    text
+}
+
+fn decode(content: &str, lenient: bool) -> Result<Vec<u8>, DecodeError> {
+    awatalk::decode(content, lenient).map(|instructions| make_object_vec(&instructions))
+}
+
+fn error(message: &str, offset: usize, bit: usize) -> DecodeError {
+    DecodeError {
+        message: message.to_string(),
+        offset,
+        bit,
+    }
+}
+
+#[test]
+fn round_trip() {
+    let object =
+        pipeline::assemble_awasm("blo -5\npr1\nsrn 3\nlbl 7\nred", "", &Options::default())
+            .unwrap();
+    assert_eq!(decode(&object_to_awa(&object), false).unwrap(), object);
+    // comments, case and other characters are ignored, only spaces separate words
+    assert_eq!(
+        decode(
+            "AWA ; header\n awa awa awawa awa ; pr1\nx aWa awa awawa awa",
+            false
+        )
+        .unwrap(),
+        [0x02, 0x00, 0x02, 0x00]
+    );
+    // nop stays a nop
+    assert_eq!(decode(&awa("00000"), false).unwrap(), [0x00, 0x00]);
+}
+
+#[test]
+fn header_errors() {
+    let message = "Awatalk must start with 'awa'";
+    assert_eq!(decode("", false), Err(error(message, 0, 0)));
+    assert_eq!(decode("  wawa awa", false), Err(error(message, 2, 0)));
+    // the header is required even when lenient
+    assert_eq!(decode("wawa", true), Err(error(message, 0, 0)));
+}
+
+#[test]
+fn invalid_token() {
+    let err = decode("awa awa wawa", false).unwrap_err();
+    assert_eq!(err, error("Invalid awatalk token 'wawa'", 8, 1));
+    assert_eq!(
+        err.to_string(),
+        "byte 8, bit 1: Invalid awatalk token 'wawa'"
+    );
+}
+
+#[test]
+fn unknown_opcode() {
+    // pr1 then opcode 0x1B
+    assert_eq!(
+        decode(&awa("00010 11011"), false),
+        Err(error("Unknown opcode 0x1B", 21, 5))
+    );
+}
+
+#[test]
+fn truncated_instructions() {
+    assert_eq!(
+        decode(&awa("00010 01"), false),
+        Err(error("Truncated opcode, 2 of 5 bits", 22, 5))
+    );
+    // blo with 3 of its 8 argument bits, reported where the instruction starts
+    assert_eq!(
+        decode(&awa("00101 101"), false),
+        Err(error(
+            "Truncated argument of opcode 0x05, 3 of 8 bits",
+            4,
+            0
+        ))
+    );
+}
+
+#[test]
+fn lenient() {
+    let pr1 = "00010";
+    // stops at the first invalid token
+    assert_eq!(
+        decode(&format!("{} wawa {}", awa(pr1), awa(pr1)), true).unwrap(),
+        [0x02, 0x00]
+    );
+    // skips unknown opcodes and drops a truncated instruction
+    assert_eq!(
+        decode(&awa(&format!("{}11011{}101", pr1, pr1)), true).unwrap(),
+        [0x02, 0x00, 0x02, 0x00]
+    );
+}
+
+#[test]
+fn errors_from_read_program() {
+    let options = Options::default();
+    let err = pipeline::read_program("awa awa wawa".as_bytes(), Format::Awa, "prog.awa", &options)
+        .unwrap_err();
+    assert_eq!(err, "prog.awa: byte 8, bit 1: Invalid awatalk token 'wawa'");
+
+    let lenient = Options {
+        lenient: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        pipeline::read_program("awa awa wawa".as_bytes(), Format::Awa, "prog.awa", &lenient),
+        Ok(vec![])
+    );
+}
+
+/// Format `blo -5`, `pr1`, `srn 3` and check that it still decodes to the same program
+fn format(format: AwaFormat) -> String {
+    let object = pipeline::assemble_awasm("blo -5\npr1\nsrn 3", "", &Options::default()).unwrap();
+    let text = object_to_awa_formatted(&object, &format);
+    assert_eq!(decode(&text, false).unwrap(), object, "{}", text);
+    text
+}
+
+#[test]
+fn format_wrap() {
+    let wrap = AwaFormat {
+        wrap: Some(20),
+        ..Default::default()
+    };
+    // lines break between words, even inside an instruction
+    assert_eq!(
+        format(wrap),
+        "\
+awa awa awawa awawa
+wawawawawa awawawa
+ awa awa awawa awa
+ awawa awa awawa awa
+ awa awawawa
+"
+    );
+}
+
+#[test]
+fn format_per_line() {
+    let per_line = AwaFormat {
+        per_line: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        format(per_line),
+        "\
+awa
+ awa awawa awawawawawawawa awawawa
+ awa awa awawa awa
+ awawa awa awawa awa awa awawawa
+"
+    );
+}
+
+#[test]
+fn format_annotate() {
+    let annotate = AwaFormat {
+        annotate: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        format(annotate),
+        "\
+awa
+ awa awawa awawawawawawawa awawawa  ; blo 251
+ awa awa awawa awa                  ; pr1
+ awawa awa awawa awa awa awawawa    ; srn 3
+"
+    );
+
+    // the comment follows the last line of a wrapped instruction
+    let wrapped = AwaFormat {
+        annotate: true,
+        wrap: Some(16),
+        ..Default::default()
+    };
+    assert_eq!(
+        format(wrapped),
+        "\
+awa
+ awa awawa awawa
+wawawawawa awawa
+wa                ; blo 251
+ awa awa awawa
+ awa              ; pr1
+ awawa awa awawa
+ awa awa awawawa  ; srn 3
+"
+    );
+}
+
+/// Awatalk of an awasm program
+fn awatalk(source: &str) -> String {
+    object_to_awa(&pipeline::assemble_awasm(source, "", &Options::default()).unwrap())
+}
+
+/// Exit code of awatalk run while it is decoded
+fn run_decoded(content: &str) -> Result<Option<i32>, RuntimeError> {
+    let mut interpreter = Interpreter::new("");
+    interpreter.run_instructions(Instructions::new(content.as_bytes(), false))?;
+    Ok(interpreter.exit_code())
+}
+
+#[test]
+fn run_while_decoding() {
+    // instructions run as they are pulled, the exit comes before the endless nops end
+    let pulled = Cell::new(0);
+    let exit = awatalk::decode(&awatalk("blo 3\nblo 0\nsys"), false).unwrap();
+    let nops = iter::repeat_with(|| Instruction {
+        awatism: Awatism::Nop,
+    });
+    let instructions = exit.into_iter().chain(nops).map(|instruction| {
+        pulled.set(pulled.get() + 1);
+        Ok::<_, DecodeError>(instruction)
+    });
+    let mut interpreter = Interpreter::new("");
+    interpreter.run_instructions(instructions).unwrap();
+    assert_eq!(interpreter.exit_code(), Some(3));
+    assert_eq!(pulled.get(), 3);
+}
+
+#[test]
+fn run_decoded_jumps() {
+    // forward jro and a comparison skipping a jro read ahead
+    let source = "\
+blo 1
+blo 2
+!ifeq same
+jro end
+same:
+blo 9
+blo 0
+sys
+end:
+blo 7
+blo 0
+sys
+";
+    assert_eq!(run_decoded(&awatalk(source)).unwrap(), Some(7));
+    // a jro past the end stops the program, further out is an error
+    assert_eq!(
+        run_decoded(&awatalk("blo 3\njro\nblo 9\nblo 0\nsys")).unwrap(),
+        None
+    );
+    assert!(matches!(
+        run_decoded(&awatalk(
+            "blo 5
+jro
+pr1"
+        )),
+        Err(RuntimeError::JumpOutOfRange(7))
+    ));
+}
+
+#[test]
+fn run_decoded_errors() {
+    // instructions before an invalid word run first
+    let content = format!("{} wawa", awatalk("blo 1\npr1"));
+    let err = run_decoded(&content).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "byte {}, bit 18: Invalid awatalk token 'wawa'",
+            content.len() - 4
+        )
+    );
+    assert!(matches!(err, RuntimeError::InvalidProgram(_)));
+
+    // an exit before the invalid word ends the program first
+    let content = format!("{} wawa", awatalk("blo 4\nblo 0\nsys"));
+    assert_eq!(run_decoded(&content).unwrap(), Some(4));
+
+    // run_program reports decode errors like read_program
+    let mut interpreter = Interpreter::new("");
+    let err = pipeline::run_program(
+        "awa awa wawa".as_bytes(),
+        Format::Awa,
+        "prog.awa",
+        &Options::default(),
+        &mut interpreter,
+    )
+    .unwrap_err();
+    assert_eq!(err, "prog.awa: byte 8, bit 1: Invalid awatalk token 'wawa'");
+    let err = pipeline::run_program(
+        awatalk("blo 9\nsys").as_bytes(),
+        Format::Awa,
+        "prog.awa",
+        &Options::default(),
+        &mut interpreter,
+    )
+    .unwrap_err();
+    assert_eq!(err, "Runtime error: Unknown syscall 9");
+}
@@ -0,0 +1,197 @@
+use awa5_rs::bindgen::{self, BindType};
+use awa5_rs::pipeline::{self, Options};
+
+const BINDINGS: &str = "\
+i32 add(i32 a, i32 b)   # calls add_impl
+void initwindow(i32 width, i32 height, str title) = InitWindow
+void initwindow2(i32 width, i32 height, str title) = InitWindow
+hello()
+";
+
+#[test]
+fn parse_bindings() {
+    let bindings = bindgen::parse_bindings(BINDINGS).unwrap();
+    let summary: Vec<(&str, &str, BindType, usize)> = bindings
+        .iter()
+        .map(|b| (b.name.as_str(), b.target.as_str(), b.ret, b.params.len()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("add", "add_impl", BindType::I32, 2),
+            ("initwindow", "InitWindow", BindType::Void, 3),
+            ("initwindow2", "InitWindow", BindType::Void, 3),
+            ("hello", "hello_impl", BindType::Void, 0),
+        ]
+    );
+    assert_eq!(
+        bindings[1].params,
+        [
+            (BindType::I32, "width".to_string()),
+            (BindType::I32, "height".to_string()),
+            (BindType::Str, "title".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn parse_errors() {
+    for (content, expected) in [
+        (
+            "i32 add(i32 a, i32 a)",
+            "line 1: Parameter 'a' declared twice",
+        ),
+        ("\nadd(i64 a)", "line 2: Unknown parameter type 'i64'"),
+        ("str name()", "line 1: Unsupported return type for 'name'"),
+        ("f() = 1f", "line 1: Invalid target function '1f'"),
+        ("f(i32 data)", "line 1: Invalid parameter name 'data'"),
+        ("f()\nf()", "line 2: 'f' declared twice"),
+    ] {
+        assert_eq!(bindgen::parse_bindings(content).unwrap_err(), expected);
+    }
+}
+
+#[test]
+fn generate_header() {
+    let bindings = bindgen::parse_bindings(BINDINGS).unwrap();
+    assert_eq!(
+        bindgen::generate_header(&bindings, "my-lib"),
+        "\
+// generated by awa5_rs, do not edit
+#ifndef MY_LIB_H
+#define MY_LIB_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+int32_t add_impl(int32_t a, int32_t b);
+void InitWindow(int32_t width, int32_t height, const char *title);
+void hello_impl(void);
+
+void add(const uint8_t *data, uint8_t **out, size_t *out_len);
+void initwindow(const uint8_t *data, uint8_t **out, size_t *out_len);
+void initwindow2(const uint8_t *data, uint8_t **out, size_t *out_len);
+void hello(const uint8_t *data, uint8_t **out, size_t *out_len);
+
+#endif
+"
+    );
+}
+
+#[test]
+fn generate_c() {
+    let bindings = bindgen::parse_bindings(BINDINGS).unwrap();
+    let c = bindgen::generate_c(&bindings[1..], "my-lib.h");
+    assert_eq!(
+        c,
+        "\
+// generated by awa5_rs, do not edit
+#include \"my-lib.h\"
+
+#include <stdlib.h>
+#include <string.h>
+
+void initwindow(const uint8_t *data, uint8_t **out, size_t *out_len)
+{
+    int32_t width;
+    int32_t height;
+    const char *title;
+
+    memcpy(&width, data, sizeof(width));
+    data += sizeof(width);
+
+    memcpy(&height, data, sizeof(height));
+    data += sizeof(height);
+
+    title = (const char *)data;
+    data += strlen(title) + 1;
+
+    InitWindow(width, height, title);
+    (void)out;
+    (void)out_len;
+}
+
+void initwindow2(const uint8_t *data, uint8_t **out, size_t *out_len)
+{
+    int32_t width;
+    int32_t height;
+    const char *title;
+
+    memcpy(&width, data, sizeof(width));
+    data += sizeof(width);
+
+    memcpy(&height, data, sizeof(height));
+    data += sizeof(height);
+
+    title = (const char *)data;
+    data += strlen(title) + 1;
+
+    InitWindow(width, height, title);
+    (void)out;
+    (void)out_len;
+}
+
+void hello(const uint8_t *data, uint8_t **out, size_t *out_len)
+{
+    (void)data;
+
+    hello_impl();
+    (void)out;
+    (void)out_len;
+}
+"
+    );
+
+    let c = bindgen::generate_c(&bindings[..1], "my-lib.h");
+    assert!(
+        c.contains("    int32_t result = add_impl(a, b);\n\n    *out = (uint8_t *)malloc(sizeof(result));\n"),
+        "{}",
+        c
+    );
+}
+
+#[test]
+fn generate_awasm() {
+    let bindings = bindgen::parse_bindings("i32 add(i32 a, bubble b)\nhello()\n").unwrap();
+    let awasm = bindgen::generate_awasm(&bindings);
+    assert_eq!(
+        awasm,
+        "\
+; generated by awa5_rs, do not edit
+!once
+!export add, hello
+
+!def add(a, b)
+!str \"add\"
+!_i32 $a
+blo 5
+blo $b
+srn 2
+srn 2
+srn 2
+lib
+!end
+
+!def hello
+!str \"hello\"
+srn 1
+lib
+!end
+"
+    );
+
+    // the macros expand to the same code as writing the call by hand
+    let called = pipeline::assemble_awasm(
+        &format!("{}!add 40, 2\n!hello\n", awasm),
+        "",
+        &Options::default(),
+    )
+    .unwrap();
+    let by_hand = pipeline::assemble_awasm(
+        "!str \"add\"\n!_i32 40\nblo 5\nblo 2\nsrn 2\nsrn 2\nsrn 2\nlib\n!str \"hello\"\nsrn 1\nlib\n",
+        "",
+        &Options::default(),
+    )
+    .unwrap();
+    assert_eq!(called, by_hand);
+}
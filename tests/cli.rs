@@ -0,0 +1,85 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use awa5_rs::pipeline::{self, Format, Options};
+
+/// Run the awa5_rs binary with `args`, writing `stdin` to it
+fn awa5_rs(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_awa5_rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn argv_and_exit_code() {
+    // argc, the first argument as AWA-SCII and the second as ascii, then exit with 3
+    let dir = common::temp_dir(
+        "cli-argv",
+        &[(
+            "argv.awasm",
+            "blo 1\nsys\npr1\nblo 0\nblo 3\nsys\nprn\nblo 1\nblo 2\nsys\npr1\nblo 3\nblo 0\nsys\n",
+        )],
+    );
+    let program = dir.join("argv.awasm");
+    let program = program.to_str().unwrap();
+
+    for args in [
+        vec!["run", program, "--", "awa", "hi"],
+        vec![program, "--", "awa", "hi"],
+    ] {
+        let output = awa5_rs(&args, b"");
+        assert_eq!(stdout(&output), "2 awa104 105 ", "{:?}", output);
+        assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    }
+
+    // without an exit code the status is 0, and runtime errors exit with 1
+    let output = awa5_rs(&["run", "-s", "blo 1\npr1", "--awasm"], b"");
+    assert_eq!(stdout(&output), "1 ");
+    assert_eq!(output.status.code(), Some(0));
+    let output = awa5_rs(&["run", "-s", "blo 9\nsys", "--awasm"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Unknown syscall 9"),
+        "{:?}",
+        output
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn program_from_stdin() {
+    let source = "!str a\"awa\"\nprn\nblo 3\nblo 0\nsys\n";
+    let object = pipeline::assemble_awasm(source, "", &Options::default()).unwrap();
+    let awatalk = pipeline::write_program(&object, Format::Awa, &Options::default());
+
+    // each format is detected from the piped content
+    for input in [source.as_bytes(), &awatalk, &object] {
+        for args in [vec!["run", "-"], vec!["-"]] {
+            let output = awa5_rs(&args, input);
+            assert_eq!(stdout(&output), "awa", "{:?}", output);
+            assert_eq!(output.status.code(), Some(3), "{:?}", output);
+        }
+    }
+
+    // or given with --from
+    let output = awa5_rs(&["run", "--from", "awa", "-"], &awatalk);
+    assert_eq!(stdout(&output), "awa", "{:?}", output);
+    let output = awa5_rs(&["convert", "--to", "awasm", "-"], &object);
+    assert_eq!(
+        stdout(&output),
+        "blo 2\nblo 3\nblo 2\nsrn 3\nprn\nblo 3\nblo 0\nsys\n"
+    );
+}
@@ -0,0 +1,35 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+
+use awa5_rs::pipeline::{self, Options};
+use awa5_rs::Compiler;
+
+/// Object code of an awasm program
+pub fn object(source: &str) -> Vec<u8> {
+    pipeline::assemble_awasm(source, "", &Options::default()).unwrap()
+}
+
+/// Error assembling an awasm program named `prog.awasm`
+pub fn assemble_err(source: &str) -> String {
+    pipeline::assemble_awasm(source, "prog.awasm", &Options::default()).unwrap_err()
+}
+
+/// Exit code an awasm program sets
+pub fn exit_code(source: &str) -> Option<i32> {
+    Compiler::new().compile_str(source).unwrap().run().unwrap()
+}
+
+/// Fresh temporary directory named after the test, holding `files` as `(path, content)`
+pub fn temp_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("awa5_rs-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use awa5_rs::pipeline::{self, Options};
+
+fn awasm_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            awasm_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "awasm") {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn examples_assemble() {
+    let mut files = Vec::new();
+    awasm_files(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("examples"),
+        &mut files,
+    );
+    assert!(!files.is_empty());
+
+    for file in files {
+        // examples include their neighbours with <...>
+        let options = Options {
+            include_paths: file.parent().unwrap().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let (result, _) = pipeline::assemble_awasm_file(file.to_str().unwrap(), &options);
+        if let Err(err) = result {
+            panic!("{}: {}", file.display(), err);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::fs;
+use std::io::Read;
+
+use awa5_rs::pipeline::{self, Detection, Format, Options};
+
+fn detect(sample: &[u8]) -> (Format, f32) {
+    let Detection { format, confidence } = Format::detect(sample);
+    (format, confidence)
+}
+
+#[test]
+fn detect_awasm() {
+    let source = b"; comment\nmain:\n  blo 1\n!str \"awa\"\n\npr1 ; print\n";
+    assert_eq!(detect(source), (Format::Awasm, 1.0));
+    assert_eq!(detect(b"blo 1\nfoo bar\n"), (Format::Awasm, 0.5));
+}
+
+#[test]
+fn detect_awatalk() {
+    assert_eq!(detect(b"awa awa awawa awa ; pr1\n"), (Format::Awa, 1.0));
+    assert_eq!(detect(b"awa awa awawa hello\n"), (Format::Awa, 0.75));
+}
+
+#[test]
+fn detect_objects() {
+    // blo 1, pr1
+    assert_eq!(detect(&[0x05, 0x01, 0x02, 0x00]), (Format::Object, 1.0));
+    // an unknown opcode lowers the confidence
+    assert_eq!(detect(&[0x05, 0x01, 0x1B, 0x00]), (Format::Object, 0.5));
+
+    let dir = common::temp_dir("detect", &[("prog.awasm", "main:\njro main\n")]);
+    let path = dir.join("prog.awasm");
+    let (object, _) =
+        pipeline::assemble_relocatable(path.to_str().unwrap(), &Options::default(), false).unwrap();
+    assert_eq!(detect(&object.to_bytes()), (Format::Object, 1.0));
+    // and a file without a known extension by its content
+    let renamed = path.with_extension("bin");
+    fs::write(&renamed, object.to_bytes()).unwrap();
+    assert_eq!(
+        Format::of_file(renamed.to_str().unwrap()),
+        Some(Format::Object)
+    );
+    assert_eq!(Format::of_file(path.to_str().unwrap()), Some(Format::Awasm));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn detect_truncated_utf8() {
+    let source = "blo 1\n!str \"é\"\n".as_bytes();
+    let cut = source.iter().position(|&b| b >= 0x80).unwrap() + 1;
+    // a sample cut inside a character is still text
+    assert_eq!(detect(&source[..cut]), (Format::Awasm, 1.0));
+    // invalid UTF-8 elsewhere is not
+    assert_eq!(detect(b"blo 1\n\xff\xfe\n").0, Format::Object);
+}
+
+#[test]
+fn detect_reader_keeps_the_program() {
+    let source = "blo 1\npr1\n".repeat(1000);
+    let (detection, mut reader) = pipeline::detect_reader(source.as_bytes()).unwrap();
+    assert_eq!(detection.format, Format::Awasm);
+    let mut read = String::new();
+    reader.read_to_string(&mut read).unwrap();
+    assert_eq!(read, source);
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use awa5_rs::pipeline::{self, Options};
+use awa5_rs::sandbox::Policy;
+use awa5_rs::Compiler;
+
+fn assemble(dir: &Path, main: &str) -> Result<Vec<u8>, String> {
+    let path = dir.join(main);
+    pipeline::assemble_awasm_file(path.to_str().unwrap(), &Options::default()).0
+}
+
+#[test]
+fn relative_include_from_parent_directory() {
+    let dir = common::temp_dir(
+        "relative",
+        &[
+            ("app/main.awasm", "!include \"../shared/x.awasm\"\npr1\n"),
+            ("shared/x.awasm", "blo 1\n"),
+        ],
+    );
+    assert_eq!(
+        assemble(&dir, "app/main.awasm").unwrap(),
+        [0x05, 1, 0x02, 0]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn once_by_canonical_path() {
+    let dir = common::temp_dir(
+        "once",
+        &[
+            (
+                "main.awasm",
+                "!include \"lib/x.awasm\"\n!include \"lib/../lib/x.awasm\"\n",
+            ),
+            ("lib/x.awasm", "!once\nx:\nblo 1\n"),
+        ],
+    );
+    assert_eq!(assemble(&dir, "main.awasm").unwrap(), [0x05, 1]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn include_cycle() {
+    let dir = common::temp_dir(
+        "cycle",
+        &[
+            ("a.awasm", "!include \"b.awasm\"\n"),
+            ("b.awasm", "!include \"a.awasm\"\n"),
+        ],
+    );
+    let err = assemble(&dir, "a.awasm").unwrap_err();
+    assert!(err.contains("Include cycle"), "{}", err);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Project with `allowed/lib.awasm`, `allowed/ok.awasm`, `secret/s.awasm` and `main`
+fn policy_project(name: &str, main: &str) -> PathBuf {
+    common::temp_dir(
+        name,
+        &[
+            ("allowed/lib.awasm", "!include \"../secret/s.awasm\"\n"),
+            ("allowed/ok.awasm", "blo 1\n"),
+            ("secret/s.awasm", "blo 2\n"),
+            ("main.awasm", main),
+        ],
+    )
+}
+
+/// Compiler whose policy allows includes from `allowed` only
+fn restricted(root: &Path) -> Compiler {
+    Compiler::new().policy(Policy {
+        include_dirs: Some(vec![root.join("allowed")]),
+        ..Default::default()
+    })
+}
+
+fn compile(compiler: Compiler, root: &Path) -> Result<(), String> {
+    compiler
+        .compile_file(root.join("main.awasm").to_str().unwrap())
+        .map(|_| ())
+}
+
+#[test]
+fn include_inside_allowed_dirs() {
+    let root = policy_project("allowed", "!include \"allowed/ok.awasm\"\n");
+    assert!(compile(restricted(&root), &root).is_ok());
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn relative_include_outside_allowed_dirs() {
+    let root = policy_project("outside", "!include \"secret/s.awasm\"\n");
+    let err = compile(restricted(&root), &root).unwrap_err();
+    assert!(
+        err.contains("outside the allowed include directories"),
+        "{}",
+        err
+    );
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn parent_include_outside_allowed_dirs() {
+    let root = policy_project("parent", "!include \"allowed/lib.awasm\"\n");
+    let err = compile(restricted(&root), &root).unwrap_err();
+    assert!(err.contains("\"../secret/s.awasm\""), "{}", err);
+    assert!(
+        err.contains("outside the allowed include directories"),
+        "{}",
+        err
+    );
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn search_path_include_outside_allowed_dirs() {
+    let root = policy_project("search", "!include <s.awasm>\n");
+    let compiler = restricted(&root).include_path(root.join("secret").to_str().unwrap());
+    let err = compile(compiler, &root).unwrap_err();
+    assert!(
+        err.contains("Include path") && err.contains("outside the allowed include directories"),
+        "{}",
+        err
+    );
+    fs::remove_dir_all(root).unwrap();
+}
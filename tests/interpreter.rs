@@ -0,0 +1,151 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use awa5_rs::dynlib::LibArg;
+use awa5_rs::interpreter::RuntimeError;
+use awa5_rs::pipeline::{self, Options};
+use awa5_rs::sandbox::{Limits, Policy};
+use awa5_rs::{Compiler, Interpreter};
+
+/// Run a program under the limits given as for `--limits`
+fn run_limited(source: &str, limits: &str) -> Result<Option<i32>, RuntimeError> {
+    let policy = Policy {
+        limits: Limits::parse(limits).unwrap(),
+        ..Default::default()
+    };
+    Compiler::new()
+        .policy(policy)
+        .compile_str(source)
+        .unwrap()
+        .run()
+}
+
+/// Push `second` above `first`, branch to an exit with 7 when taken and exit with 9 after
+/// falling through
+fn branch(test: &str, first: i32, second: i32) -> Option<i32> {
+    common::exit_code(&format!(
+        "blo {}\nblo {}\n!{} yes\nblo 9\nblo 0\nsys\nyes:\nblo 7\nblo 0\nsys",
+        first, second, test
+    ))
+}
+
+#[test]
+fn ifeq_branches() {
+    assert_eq!(branch("ifeq", 4, 4), Some(7));
+    assert_eq!(branch("ifeq", 4, 5), Some(9));
+}
+
+#[test]
+fn iflss_branches() {
+    assert_eq!(branch("iflss", 5, 3), Some(7));
+    assert_eq!(branch("iflss", 3, 5), Some(9));
+    assert_eq!(branch("iflss", 4, 4), Some(9));
+}
+
+#[test]
+fn ifgr8_branches() {
+    assert_eq!(branch("ifgr8", 3, 5), Some(7));
+    assert_eq!(branch("ifgr8", 5, 3), Some(9));
+    assert_eq!(branch("ifgr8", 4, 4), Some(9));
+}
+
+#[test]
+fn jro_to_first_instruction() {
+    assert!(matches!(
+        run_limited("loop:\njro loop", "instructions=100"),
+        Err(RuntimeError::InstructionLimit(100))
+    ));
+}
+
+#[test]
+fn jro_past_last_instruction_stops() {
+    assert_eq!(common::exit_code("blo 0\njro\nblo 9\nblo 0\nsys"), Some(9));
+    assert_eq!(common::exit_code("blo 3\njro\nblo 9\nblo 0\nsys"), None);
+}
+
+#[test]
+fn jro_out_of_range() {
+    assert!(matches!(
+        run_limited("blo 4\njro\nblo 9\nblo 0\nsys", ""),
+        Err(RuntimeError::JumpOutOfRange(6))
+    ));
+    assert!(matches!(
+        run_limited("!i32 -100\njro", ""),
+        Err(RuntimeError::JumpOutOfRange(-94))
+    ));
+}
+
+#[test]
+fn instruction_limit() {
+    assert!(run_limited("blo 1\nblo 2\nblo 3", "instructions=3").is_ok());
+    assert!(matches!(
+        run_limited("blo 1\nblo 2\nblo 3\nblo 4", "instructions=3"),
+        Err(RuntimeError::InstructionLimit(3))
+    ));
+}
+
+#[test]
+fn abyss_limit() {
+    assert!(matches!(
+        run_limited("loop:\nblo 1\njro loop", "abyss=8"),
+        Err(RuntimeError::AbyssLimit(8))
+    ));
+}
+
+#[test]
+fn timeout() {
+    let err = run_limited("loop:\njro loop", "timeout=10").unwrap_err();
+    assert!(matches!(err, RuntimeError::Timeout(_)));
+    assert!(err.is_limit());
+}
+
+#[test]
+fn syscall_without_service_number() {
+    // an empty abyss or a double bubble on top
+    for source in ["sys", "blo 0\nsrn 1\nsys"] {
+        let err = run_limited(source, "").unwrap_err();
+        assert!(
+            matches!(err, RuntimeError::MissingSyscallService),
+            "{}",
+            err
+        );
+    }
+    // the exit service without an exit code
+    let err = run_limited("blo 0\nsys", "").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Syscall exit failed: Expected a simple bubble"
+    );
+}
+
+#[test]
+fn time_syscall() {
+    let seconds = Rc::new(RefCell::new(Vec::new()));
+    let recorded = seconds.clone();
+    let mut interpreter = Interpreter::new("");
+    interpreter.register_fn("test.out", move |args: &[LibArg]| {
+        recorded.borrow_mut().extend_from_slice(args);
+        Ok(vec![])
+    });
+    // pass the pushed bubble on as a word argument
+    let source = "!str \"test.out\"\nblo 0\nblo 5\nsys\nsrn 2\nsrn 1\nsrn 2\nlib\n";
+    interpreter
+        .run(pipeline::assemble_awasm(source, "", &Options::default()).unwrap())
+        .unwrap();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let args = seconds.borrow();
+    match args.as_slice() {
+        [LibArg::Word(bytes)] => {
+            let pushed = i64::from_le_bytes(<[u8; 8]>::try_from(bytes.as_slice()).unwrap());
+            assert!((now - 5..=now).contains(&pushed), "{} {}", pushed, now);
+        }
+        args => panic!("{:?}", args),
+    }
+}
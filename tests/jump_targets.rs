@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+
+use awa5_rs::assembler::make_object_vec;
+use awa5_rs::parser::awasm::{parse_lines, MacroTable};
+
+const JRO: u8 = 0x18;
+const CALL: u8 = 0x19;
+const LBL: u8 = 0x10;
+
+fn assemble(source: &str) -> Vec<(u8, u8)> {
+    let instructions = parse_lines(
+        &mut MacroTable::new(),
+        &mut HashSet::new(),
+        &mut HashSet::new(),
+        "",
+        "",
+        source.lines().map(|line| line.to_string()),
+    )
+    .unwrap();
+    make_object_vec(&instructions)
+        .chunks(2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect()
+}
+
+/// i32 pushed by the four `blo` before the `srn 4` in front of a jump
+fn pushed_i32(code: &[(u8, u8)], jump: usize) -> i32 {
+    assert_eq!(
+        code[jump - 1],
+        (0x09, 4),
+        "srn 4 before instruction {}",
+        jump
+    );
+    let bytes: Vec<u8> = code[jump - 5..jump - 1]
+        .iter()
+        .map(|&(op, arg)| {
+            assert_eq!(op, 0x05, "blo before instruction {}", jump);
+            arg
+        })
+        .collect();
+    i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn marker(code: &[(u8, u8)], id: u8) -> usize {
+    code.iter()
+        .position(|&instruction| instruction == (LBL, id))
+        .unwrap_or_else(|| panic!("missing marker lbl {}", id))
+}
+
+/// Instructions executed after each `jro` and `call`, in program order
+fn targets(code: &[(u8, u8)]) -> Vec<usize> {
+    let mut targets = Vec::new();
+    for (index, &(op, _)) in code.iter().enumerate() {
+        match op {
+            // the interpreter steps one past index + offset
+            JRO => targets.push((index as i32 + pushed_i32(code, index) + 1) as usize),
+            // a call jumps to the address exactly
+            CALL if index >= 5 && code[index - 1] == (0x09, 4) => {
+                targets.push(pushed_i32(code, index) as usize)
+            }
+            _ => {}
+        }
+    }
+    targets
+}
+
+fn assert_targets(source: &str, markers: &[u8]) {
+    let code = assemble(source);
+    let expected: Vec<usize> = markers.iter().map(|&id| marker(&code, id)).collect();
+    assert_eq!(targets(&code), expected, "\n{}", source);
+}
+
+#[test]
+fn forward_jro() {
+    assert_targets("jro end\npop\nend:\nlbl 1", &[1]);
+}
+
+#[test]
+fn backward_jro() {
+    assert_targets("start:\nlbl 1\npop\njro start", &[1]);
+}
+
+#[test]
+fn jro_to_label_after_labels() {
+    assert_targets("a:\nb:\nc:\npop\njro end\nd:\ne:\nend:\nlbl 1", &[1]);
+}
+
+#[test]
+fn backward_jro_after_labels() {
+    assert_targets("a:\nb:\nstart:\nlbl 1\nc:\nd:\njro start", &[1]);
+}
+
+#[test]
+fn jro_after_bare_call() {
+    assert_targets("call\ncall\njro end\ncall\nend:\nlbl 1", &[1]);
+    assert_targets("start:\nlbl 1\ncall\ncall\njro start", &[1]);
+}
+
+#[test]
+fn jro_after_label_call() {
+    assert_targets("call f\njro end\nf:\nlbl 1\nret\nend:\nlbl 2", &[1, 2]);
+}
+
+#[test]
+fn jro_after_jro() {
+    assert_targets(
+        "jro b\na:\nlbl 1\njro c\nb:\nlbl 2\njro a\nc:\nlbl 3",
+        &[2, 3, 1],
+    );
+}
+
+#[test]
+fn several_jumps_to_one_label() {
+    assert_targets(
+        "jro end\npop\njro end\nstart:\nlbl 1\njro end\njro start\nend:\nlbl 2\njro start",
+        &[2, 2, 2, 1, 1],
+    );
+}
+
+#[test]
+fn jro_to_self() {
+    let code = assemble("pop\nloop:\njro loop");
+    assert_eq!(targets(&code), vec![1]);
+}
+
+#[test]
+fn call_targets() {
+    assert_targets(
+        "jro main\nf:\nlbl 1\nret\ng:\nlbl 2\nret\nmain:\nlbl 3\ncall\ncall g\na:\ncall f\ncall g",
+        &[3, 2, 1, 2],
+    );
+}
+
+#[test]
+fn backward_call_after_labels_and_bare_calls() {
+    assert_targets("x:\ny:\nf:\nlbl 1\ncall\nz:\ncall\ncall f", &[1]);
+}
+
+#[test]
+fn local_labels() {
+    assert_targets(
+        "a:\n.loop:\nlbl 1\njro .end\njro .loop\n.end:\nlbl 2\nb:\n.loop:\nlbl 3\njro .loop\njro a.end",
+        &[2, 1, 3, 2],
+    );
+}
+
+#[test]
+fn macro_local_labels() {
+    assert_targets(
+        "!def skip(id)\n    jro @done\n    pop\n@done:\n    lbl $id\n!end\n!skip 1\n!skip 2\n!skip 3",
+        &[1, 2, 3],
+    );
+}
+
+#[test]
+fn jumps_around_builtin_macros() {
+    assert_targets(
+        "jro end\n!i32 1234\n!str \"abc\"\nstart:\nlbl 1\n!libcall \"std.rand\"\nend:\nlbl 2\n!ifeq start",
+        &[2, 1],
+    );
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use awa5_rs::dynlib::LibArg;
+use awa5_rs::interpreter::{Bubble, RuntimeError};
+use awa5_rs::pipeline::{self, Options};
+use awa5_rs::Interpreter;
+
+/// Run a program, returning the exit code it set
+fn run(interpreter: &mut Interpreter, source: &str) -> Result<Option<i32>, RuntimeError> {
+    interpreter.run(pipeline::assemble_awasm(source, "", &Options::default()).unwrap())?;
+    Ok(interpreter.exit_code())
+}
+
+/// Call `fn_name` with the single argument built by `arg`, which leaves a tag and a value
+fn call_with_arg(fn_name: &str, arg: &str) -> Result<Option<i32>, RuntimeError> {
+    let mut interpreter = Interpreter::new("");
+    interpreter.register_fn("test.fn", |_: &[LibArg]| Ok(vec![]));
+    run(
+        &mut interpreter,
+        &format!("!str \"{}\"\n{}\nsrn 2\nsrn 1\nsrn 2\nlib", fn_name, arg),
+    )
+}
+
+fn assert_invalid_args(result: Result<Option<i32>, RuntimeError>, expected: &str) {
+    match result {
+        Err(RuntimeError::InvalidLibArgs(_, err)) => assert!(err.contains(expected), "{}", err),
+        result => panic!(
+            "Expected invalid arguments, got {:?}",
+            result.map_err(|e| e.to_string())
+        ),
+    }
+}
+
+#[test]
+fn invalid_arg_type() {
+    assert_invalid_args(call_with_arg("test.fn", "blo 9\nblo 1"), "Invalid type 9");
+    // shared library calls decode their arguments before looking the function up
+    assert_invalid_args(call_with_arg("missing", "blo 9\nblo 1"), "Invalid type 9");
+}
+
+#[test]
+fn invalid_awascii_arg() {
+    assert_invalid_args(
+        call_with_arg("test.fn", "blo 1\nblo 99"),
+        "99 is not an AWA-SCII character",
+    );
+    assert_invalid_args(
+        call_with_arg("test.fn", "blo 3\nblo 1\nblo 99\nsrn 2"),
+        "99 is not an AWA-SCII character",
+    );
+    assert_invalid_args(
+        call_with_arg("test.fn", "blo 3\nblo 1"),
+        "Expected a string",
+    );
+}
+
+#[test]
+fn arg_without_type() {
+    let mut interpreter = Interpreter::new("");
+    interpreter.register_fn("test.fn", |_: &[LibArg]| Ok(vec![]));
+    assert_invalid_args(
+        run(
+            &mut interpreter,
+            "!str \"test.fn\"\nblo 1\nsrn 1\nsrn 2\nlib",
+        ),
+        "Expected a type tag and a value for argument 0",
+    );
+}
+
+#[test]
+fn registered_fn_called_with_lib() {
+    let mut interpreter = Interpreter::new("");
+    interpreter.register_fn("test.fn", |args: &[LibArg]| {
+        assert_eq!(
+            args,
+            [
+                LibArg::Word(5i32.to_le_bytes().to_vec()),
+                LibArg::AwaChar('W'),
+                LibArg::AwaStr("awa".to_string()),
+                LibArg::Str(b"awa".to_vec()),
+            ]
+        );
+        Ok(vec![Bubble::Simple(3)])
+    });
+    let code = run(
+        &mut interpreter,
+        "!libcall \"test.fn\", 5, a'W', a\"awa\", \"awa\"\nblo 0\nsys",
+    );
+    assert_eq!(code.unwrap(), Some(3));
+}
+
+/// Shared library exporting `answer`, which returns the byte 42, `None` without a C compiler
+fn answer_library() -> Option<String> {
+    let dir = common::temp_dir(
+        "lib",
+        &[(
+            "answer.c",
+            "#include <stdint.h>\n#include <stdlib.h>\n\
+             void answer(const uint8_t *data, uint8_t **out, size_t *out_len)\n\
+             { *out = malloc(1); (*out)[0] = 42; *out_len = 1; }\n",
+        )],
+    );
+    let source = dir.join("answer.c");
+    let status = Command::new("cc")
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(dir.join(format!("libanswer.{}", std::env::consts::DLL_EXTENSION)))
+        .arg(&source)
+        .status()
+        .ok()?;
+    status.success().then(|| dir.to_str().unwrap().to_string())
+}
+
+#[test]
+fn registered_fn_takes_precedence() {
+    let Some(dir) = answer_library() else {
+        eprintln!("skipped, could not build a shared library with cc");
+        return;
+    };
+    let program = "!libcall \"answer\"\nblo 0\nsys";
+
+    let mut interpreter = Interpreter::new(&dir);
+    assert_eq!(run(&mut interpreter, program).unwrap(), Some(42));
+
+    let mut interpreter = Interpreter::new(&dir);
+    interpreter.register_fn("answer", |_: &[LibArg]| Ok(vec![Bubble::Simple(7)]));
+    assert_eq!(run(&mut interpreter, program).unwrap(), Some(7));
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use awa5_rs::linker::{self, ObjectFile};
+use awa5_rs::pipeline::{self, Options};
+use awa5_rs::Interpreter;
+
+/// Assemble each file to a relocatable object named after it
+fn objects(dir: &Path, names: &[&str]) -> Vec<(String, ObjectFile)> {
+    names
+        .iter()
+        .map(|name| {
+            let path = dir.join(name);
+            let (object, _) =
+                pipeline::assemble_relocatable(path.to_str().unwrap(), &Options::default(), false)
+                    .unwrap();
+            // objects are written to disk and read back by the CLI
+            let object = ObjectFile::from_bytes(&object.to_bytes()).unwrap();
+            (name.replace(".awasm", ".o"), object)
+        })
+        .collect()
+}
+
+#[test]
+fn link_across_objects() {
+    let dir = common::temp_dir(
+        "link",
+        &[
+            ("main.awasm", "main:\njro other\n.local:\n"),
+            (
+                "other.awasm",
+                "other:\njro .local\n.local:\nblo 5\nblo 0\nsys\n",
+            ),
+        ],
+    );
+    let code = linker::link(&objects(&dir, &["main.awasm", "other.awasm"])).unwrap();
+    let mut interpreter = Interpreter::new("");
+    interpreter.run(code).unwrap();
+    assert_eq!(interpreter.exit_code(), Some(5));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn link_diagnostics() {
+    let dir = common::temp_dir(
+        "diagnostics",
+        &[
+            ("a.awasm", "start:\njro nowhere\n.x:\n"),
+            ("b.awasm", "start:\njro .x\njro elsewhere\n"),
+        ],
+    );
+    let err = linker::link(&objects(&dir, &["a.awasm", "b.awasm"])).unwrap_err();
+    assert_eq!(
+        err.lines().collect::<Vec<_>>(),
+        [
+            "Duplicate symbol 'start' in a.o and b.o",
+            "Undefined symbol 'nowhere' referenced in a.o",
+            // local labels of a.o are not visible to b.o
+            "Undefined symbol 'start.x' referenced in b.o",
+            "Undefined symbol 'elsewhere' referenced in b.o",
+        ]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn invalid_objects() {
+    assert_eq!(
+        ObjectFile::from_bytes(b"blo").unwrap_err(),
+        "Not a relocatable object"
+    );
+    let dir = common::temp_dir("invalid", &[("a.awasm", "a:\njro a\n")]);
+    let bytes = objects(&dir, &["a.awasm"]).remove(0).1.to_bytes();
+    assert!(ObjectFile::is_object(&bytes));
+    assert!(ObjectFile::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn listing() {
+    let dir = common::temp_dir(
+        "listing",
+        &[(
+            "prog.awasm",
+            "jro main\n!def two()\nblo 2\n!end\nmain:\n!two\n!str \"ab\"\n",
+        )],
+    );
+    let path = dir.join("prog.awasm");
+    let path = path.to_str().unwrap();
+    let (_, listing) = pipeline::assemble_relocatable(path, &Options::default(), true).unwrap();
+    let listing = listing.unwrap().replace(path, "prog.awasm");
+    let blo_0 = "05 00  awa awawa awawa awa awa awa awa awa awa awa awa";
+    assert_eq!(
+        listing.lines().collect::<Vec<_>>(),
+        [
+            format!("     0  {}           prog.awasm:1  jro main", blo_0),
+            format!("     1  {}", blo_0),
+            format!("     2  {}", blo_0),
+            format!("     3  {}", blo_0),
+            "     4  09 04  awawa awa awawa awa awawa awa awa".to_string(),
+            format!("     5  18 00  wawa awa awa awa{}-> main", " ".repeat(44)),
+            format!("        main:{}prog.awasm:5  main:", " ".repeat(60)),
+            format!(
+                "     6  05 02  awa awawa awawa awa awa awa awa awa awawa awa{}prog.awasm:6  !two  [!two]",
+                " ".repeat(13)
+            ),
+            format!(
+                "     7  05 62  awa awawa awawa awawawa awa awa awawa awa{}prog.awasm:7  !str \"ab\"  [!str]",
+                " ".repeat(17)
+            ),
+            format!(
+                "     8  05 61  awa awawa awawa awawawa awa awa awa awawa{}[!str]",
+                " ".repeat(19)
+            ),
+            format!(
+                "     9  09 02  awawa awa awawa awa awa awawa awa{}[!str]",
+                " ".repeat(27)
+            ),
+        ]
+    );
+
+    // without a listing nothing is recorded
+    let (_, listing) = pipeline::assemble_relocatable(path, &Options::default(), false).unwrap();
+    assert_eq!(listing, None);
+    fs::remove_dir_all(dir).unwrap();
+}
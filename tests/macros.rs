@@ -0,0 +1,99 @@
+mod common;
+
+use awa5_rs::Compiler;
+
+#[test]
+fn macro_local_labels() {
+    // every expansion jumps over its own exit
+    let skip = "!def skip()\njro @end\nblo 9\nblo 0\nsys\n@end:\n!end\n";
+    assert_eq!(
+        common::exit_code(&format!("{}!skip\n!skip\nblo 7\nblo 0\nsys", skip)),
+        Some(7)
+    );
+    assert_eq!(
+        common::assemble_err("jro @x\n@x:"),
+        "prog.awasm:1:1: Macro-local label '@x' used outside of a macro"
+    );
+}
+
+#[test]
+fn variadic_arguments() {
+    let sum = "!def sum(first, rest...)\nblo $first\n!if $# gt 0\n!sum $rest\n4dd\n!endif\n!end\n";
+    assert_eq!(
+        common::object(&format!("{}!sum 1, 2, 3", sum)),
+        common::object("blo 1\nblo 2\nblo 3\n4dd\n4dd")
+    );
+    assert_eq!(
+        common::object(&format!("{}!sum 4", sum)),
+        common::object("blo 4")
+    );
+
+    let count = "!def count(args...)\nblo $#\n!end\n";
+    assert_eq!(
+        common::object(&format!("{}!count\n!count 1, 2, 3", count)),
+        common::object("blo 0\nblo 3")
+    );
+    assert_eq!(
+        common::assemble_err("!def f(a)\nblo $a\n!end\n!f"),
+        "prog.awasm:4:1: Macro !f expected 1 arguments, received 0"
+    );
+}
+
+#[test]
+fn constants() {
+    assert_eq!(
+        common::object("!set N, 2 * 3 + 1\n!set M, N % 4\nblo N\nblo M"),
+        common::object("blo 7\nblo 3")
+    );
+    assert_eq!(
+        common::assemble_err("!set A, 5 / 0"),
+        "prog.awasm:1:1: Division by zero"
+    );
+
+    let compiler = Compiler::new().define("N", 5);
+    let program = compiler.compile_str("blo N").unwrap();
+    assert_eq!(program.object(), common::object("blo 5"));
+}
+
+#[test]
+fn conditional_assembly() {
+    let source = "!if N ge 2\nblo 1\n!else\nblo 2\n!endif";
+    assert_eq!(
+        common::object(&format!("!set N, 3\n{}", source)),
+        common::object("blo 1")
+    );
+    assert_eq!(
+        common::object(&format!("!set N, 1\n{}", source)),
+        common::object("blo 2")
+    );
+    assert_eq!(
+        common::object("!if 1 eq 1\n!if 0 ne 0\nblo 1\n!endif\nblo 2\n!endif"),
+        common::object("blo 2")
+    );
+    assert_eq!(
+        common::assemble_err("!if 1\nblo 1"),
+        "prog.awasm:1:1: Missing !endif"
+    );
+    assert_eq!(
+        common::assemble_err("!else"),
+        "prog.awasm:1:1: !else without !if"
+    );
+    assert_eq!(
+        common::assemble_err("!endif"),
+        "prog.awasm:1:1: !endif without !if"
+    );
+}
+
+#[test]
+fn expansion_depth_limit() {
+    assert_eq!(
+        common::assemble_err("!def f()\n!f\n!end\n!f"),
+        "prog.awasm:2:1: Macro !f nested deeper than 64 expansions"
+    );
+    // recursion that ends is fine
+    let down = "!def down(n)\n!if $n gt 0\n!set M, $n - 1\n!down M\n!endif\nblo $n\n!end\n";
+    assert_eq!(
+        common::object(&format!("{}!down 2", down)),
+        common::object("blo 0\nblo 1\nblo 2")
+    );
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use awa5_rs::Compiler;
+
+const MATH: &str = "\
+!once
+!export twice, push2
+
+twice:
+    sbm 1
+    blo 2
+    mul
+    sbm 1
+    ret
+
+secret:
+    ret
+
+!def push2
+    blo 2
+!end
+
+!def hidden
+    blo 3
+!end
+";
+
+/// Directory holding `math.awasm`, `other.awasm` exporting another `twice`, and `main`
+fn project(name: &str, main: &str) -> PathBuf {
+    common::temp_dir(
+        name,
+        &[
+            ("math.awasm", MATH),
+            ("other.awasm", "!export twice\ntwice:\nret\n"),
+            ("main.awasm", main),
+        ],
+    )
+}
+
+fn run(dir: &Path) -> Result<Option<i32>, String> {
+    Compiler::new()
+        .include_path(dir.to_str().unwrap())
+        .run(dir.join("main.awasm").to_str().unwrap())
+}
+
+#[test]
+fn qualified_exports() {
+    let dir = project(
+        "qualified",
+        "jro main\n!include <math.awasm>\nmain:\nblo 19\n!math::push2\n4dd\ncall math::twice\nblo 0\nsys\n",
+    );
+    assert_eq!(run(&dir).unwrap(), Some(42));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn unqualified_exports() {
+    let dir = project(
+        "unqualified",
+        "jro main\n!include <math.awasm>\nmain:\nblo 19\n!push2\n4dd\ncall twice\nblo 0\nsys\n",
+    );
+    assert_eq!(run(&dir).unwrap(), Some(42));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn not_exported() {
+    for (main, expected) in [
+        ("call math::secret", "Label 'math::secret' is not exported"),
+        ("!math::hidden", "Macro !math::hidden is not exported"),
+    ] {
+        let dir = project(
+            "private",
+            &format!("jro main\n!include <math.awasm>\nmain:\n{}\n", main),
+        );
+        let err = run(&dir).unwrap_err();
+        assert!(err.contains(expected), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // private names are not found unqualified either
+    let dir = project(
+        "unexported",
+        "jro main\n!include <math.awasm>\nmain:\n!hidden\n",
+    );
+    let err = run(&dir).unwrap_err();
+    assert!(err.contains("hidden"), "{}", err);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn ambiguous_exports() {
+    let dir = project(
+        "ambiguous",
+        "jro main\n!include <math.awasm>\n!include <other.awasm>\nmain:\ncall twice\n",
+    );
+    let err = run(&dir).unwrap_err();
+    assert!(err.contains("Label 'twice' is ambiguous"), "{}", err);
+    fs::remove_dir_all(dir).unwrap();
+
+    let dir = project(
+        "disambiguated",
+        "jro main\n!include <math.awasm>\n!include <other.awasm>\nmain:\nblo 21\ncall math::twice\nblo 0\nsys\n",
+    );
+    assert_eq!(run(&dir).unwrap(), Some(42));
+    fs::remove_dir_all(dir).unwrap();
+}
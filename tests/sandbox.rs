@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use awa5_rs::sandbox::Policy;
+
+#[test]
+fn comments_after_whitespace() {
+    let mut policy = Policy::default();
+    policy.apply("# disable_lib = true").unwrap();
+    policy.apply("disable_env = true   # no variables").unwrap();
+    policy.apply("include_dir = ./lib#2 # numbered").unwrap();
+    policy.apply("allow_fn = std.sqrt #, foo").unwrap();
+    assert!(!policy.disable_lib);
+    assert!(policy.disable_env);
+    assert_eq!(policy.include_dirs, Some(vec![PathBuf::from("./lib#2")]));
+    assert_eq!(policy.allow_fns, Some(vec!["std.sqrt".to_string()]));
+}
+
+#[test]
+fn hash_inside_value() {
+    let mut policy = Policy::default();
+    policy.apply("allow_lib=foo#bar").unwrap();
+    assert_eq!(policy.allow_libs, Some(vec!["foo#bar".to_string()]));
+}
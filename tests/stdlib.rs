@@ -0,0 +1,125 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use awa5_rs::dynlib::LibArg;
+use awa5_rs::interpreter::RuntimeError;
+use awa5_rs::pipeline::{self, Options};
+use awa5_rs::{Interpreter, StdConfig};
+
+/// Pass the result of the lib call on top of the abyss to `test.out`, tagged with `tag`
+fn out(call: &str, tag: u8) -> String {
+    format!(
+        "!str \"test.out\"\nblo {}\n{}\nsrn 2\nsrn 1\nsrn 2\nlib\n",
+        tag, call
+    )
+}
+
+/// Interpreter without shared libraries, recording the arguments `test.out` is called with
+fn interpreter() -> (Interpreter, Rc<RefCell<Vec<LibArg>>>) {
+    let mut interpreter = Interpreter::new("");
+    let outputs = Rc::new(RefCell::new(Vec::new()));
+    let recorded = outputs.clone();
+    interpreter.register_fn("test.out", move |args: &[LibArg]| {
+        recorded.borrow_mut().extend_from_slice(args);
+        Ok(vec![])
+    });
+    (interpreter, outputs)
+}
+
+fn run(interpreter: &mut Interpreter, source: &str) -> Result<(), RuntimeError> {
+    interpreter.run(pipeline::assemble_awasm(source, "", &Options::default()).unwrap())
+}
+
+#[test]
+fn itoa_awa() {
+    let (mut interpreter, outputs) = interpreter();
+    run(&mut interpreter, &out("!libcall \"std.itoa_awa\", 1234", 3)).unwrap();
+    assert_eq!(*outputs.borrow(), [LibArg::AwaStr("1234".to_string())]);
+}
+
+#[test]
+fn itoa_awa_negative() {
+    let (mut interpreter, _) = interpreter();
+    let err = run(&mut interpreter, "!libcall \"std.itoa_awa\", -5").unwrap_err();
+    assert!(
+        matches!(&err, RuntimeError::FunctionFailed(name, msg) if name == "std.itoa_awa" && msg.contains("negative")),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn itoa_negative() {
+    let (mut interpreter, outputs) = interpreter();
+    run(&mut interpreter, &out("!libcall \"std.itoa\", -5", 4)).unwrap();
+    assert_eq!(*outputs.borrow(), [LibArg::Str(b"-5".to_vec())]);
+}
+
+#[test]
+fn set_std_config_keeps_random_state() {
+    let rand = out("!libcall \"std.rand\"", 0);
+
+    let (mut expected, expected_outputs) = interpreter();
+    run(
+        &mut expected,
+        &format!("!libcall \"std.srand\", 7\n{}", rand),
+    )
+    .unwrap();
+
+    let (mut interpreter, outputs) = interpreter();
+    run(&mut interpreter, "!libcall \"std.srand\", 7").unwrap();
+    interpreter.set_std_config(&StdConfig {
+        allow_files: false,
+        file_root: None,
+    });
+    run(&mut interpreter, &rand).unwrap();
+    assert_eq!(*outputs.borrow(), *expected_outputs.borrow());
+}
+
+#[test]
+fn set_std_config_keeps_registered_functions() {
+    let (mut interpreter, _) = interpreter();
+    interpreter.register_fn("std.sqrt", |_: &[LibArg]| Err("overridden".to_string()));
+    interpreter.set_std_config(&StdConfig {
+        allow_files: false,
+        file_root: None,
+    });
+
+    // overrides stay registered
+    let err = run(&mut interpreter, "!libcall \"std.sqrt\", 2.0").unwrap_err();
+    assert!(err.to_string().contains("overridden"), "{}", err);
+    let err = run(&mut interpreter, "!libcall \"std.read_file\", \"x\"").unwrap_err();
+    assert!(
+        err.to_string().contains("File access is disabled"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn double_bubble_where_simple_expected() {
+    let (mut interpreter, _) = interpreter();
+    // a simple bubble argument holding a double bubble
+    let source = "!str \"test.out\"\nblo 5\nblo 1\nblo 2\nsrn 2\nsrn 2\nsrn 1\nsrn 2\nlib\n";
+    let err = run(&mut interpreter, source).unwrap_err();
+    assert!(
+        matches!(err, RuntimeError::InvalidLibArgs(ref name, _) if name == "test.out"),
+        "{}",
+        err
+    );
+    assert!(err.to_string().contains("argument 0"), "{}", err);
+
+    // a string holding a double bubble
+    let source =
+        "!str \"test.out\"\nblo 4\n!str \"ab\"\nblo 1\nsrn 1\nsrn 2\nsrn 2\nsrn 1\nsrn 2\nlib\n";
+    let err = run(&mut interpreter, source).unwrap_err();
+    assert!(err.to_string().contains("argument 0"), "{}", err);
+}
+
+#[test]
+fn invalid_function_name() {
+    let (mut interpreter, _) = interpreter();
+    let source = "blo -1\nblo 5\nblo 1\nsrn 2\nsrn 1\nsrn 2\nlib\n";
+    let err = run(&mut interpreter, source).unwrap_err();
+    assert!(matches!(err, RuntimeError::InvalidLibName(_)), "{}", err);
+}
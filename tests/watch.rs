@@ -0,0 +1,55 @@
+mod common;
+
+use std::fs::{self, File};
+use std::time::{Duration, SystemTime};
+
+use awa5_rs::watch::{self, Snapshot};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn child_args_without_watch() {
+    assert_eq!(
+        watch::child_args(args(&[
+            "run",
+            "--watch",
+            "prog.awasm",
+            "--",
+            "--watch",
+            "x"
+        ])),
+        args(&["run", "prog.awasm", "--", "--watch", "x"])
+    );
+    assert_eq!(
+        watch::child_args(args(&["--watch", "-i", "lib", "prog.awasm"])),
+        args(&["-i", "lib", "prog.awasm"])
+    );
+}
+
+#[test]
+fn changed_files() {
+    let dir = common::temp_dir("watch", &[("main.awasm", "blo 1\n"), ("lib.awasm", "")]);
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let files = vec![path("main.awasm"), path("lib.awasm"), path("new.awasm")];
+
+    let snapshot = Snapshot::new(files.clone());
+    assert_eq!(snapshot.changed(), None);
+
+    // a later modification time, without waiting for the file system's resolution
+    let later = SystemTime::now() + Duration::from_secs(10);
+    let lib = File::options().write(true).open(path("lib.awasm")).unwrap();
+    lib.set_modified(later).unwrap();
+    assert_eq!(snapshot.changed(), Some(path("lib.awasm").as_str()));
+
+    // files that are created or removed after the snapshot
+    let snapshot = Snapshot::new(files.clone());
+    fs::write(path("new.awasm"), "").unwrap();
+    assert_eq!(snapshot.changed(), Some(path("new.awasm").as_str()));
+    let snapshot = Snapshot::new(files);
+    fs::remove_file(path("main.awasm")).unwrap();
+    assert_eq!(snapshot.changed(), Some(path("main.awasm").as_str()));
+
+    fs::remove_dir_all(dir).unwrap();
+}